@@ -0,0 +1,188 @@
+use std::collections::BTreeSet;
+
+use itertools::Itertools;
+
+use crate::{
+    board::Board,
+    pattern::{Part, Pattern, BAG},
+    piece::Piece,
+    traits::CollectVec,
+};
+
+/// Where a 7-bag game could be, given the pieces it has dealt.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BagPosition {
+    /// How many pieces of its bag had been dealt before the first known piece.
+    pub offset: usize,
+    /// The pieces of the current bag that have already been dealt.
+    pub seen: BTreeSet<Piece>,
+}
+
+impl BagPosition {
+    /// The pieces left in the current bag, where a bag that has been dealt entirely leaves a whole new bag.
+    #[must_use]
+    pub fn remaining(&self) -> BTreeSet<Piece> {
+        let remaining: BTreeSet<Piece> =
+            BAG.into_iter().filter(|x| !self.seen.contains(x)).collect();
+        if remaining.is_empty() {
+            BAG.into_iter().collect()
+        } else {
+            remaining
+        }
+    }
+
+    /// The next `len` pieces this position can deal, which finish the current bag and then go through whole bags.
+    #[must_use]
+    pub fn continuation(&self, len: usize) -> Pattern {
+        let remaining = self.remaining();
+        let mut parts = vec![];
+        let mut left = len;
+        let first = remaining.len().min(left);
+        if first > 0 {
+            parts.push(Part::ordered_selections(&remaining, first));
+            left -= first;
+        }
+
+        let all: BTreeSet<Piece> = BAG.into_iter().collect();
+        while left > 0 {
+            let n = left.min(BAG.len());
+            parts.push(Part::ordered_selections(&all, n));
+            left -= n;
+        }
+
+        Pattern { parts }
+    }
+}
+
+/// The pieces that have been placed on a board whose cells are colored by piece.
+/// A piece that was partly cleared still counts, but pieces that were cleared entirely can't be seen.
+#[must_use]
+pub fn used_pieces(board: &Board) -> Vec<Piece> {
+    BAG.into_iter()
+        .flat_map(|p| {
+            let cells = board.rows().iter().flatten().filter(|&&x| x == p).count();
+            std::iter::repeat_n(p, cells.div_ceil(4))
+        })
+        .vec()
+}
+
+/// Every bag position a game could be in after dealing `used` in some order and then `queue`.
+/// Pieces dealt before the first known piece of its bag are unknown, so every offset into the first bag is tried.
+#[must_use]
+pub fn positions(used: &[Piece], queue: &[Piece]) -> Vec<BagPosition> {
+    let mut counts = BAG.map(|p| used.iter().filter(|&&x| x == p).count());
+    let mut found = BTreeSet::new();
+    for offset in 0..BAG.len() {
+        let search = Search {
+            offset,
+            used: used.len(),
+            queue,
+        };
+        search.assign(0, &mut counts, &mut |seen| {
+            found.insert(BagPosition { offset, seen });
+        });
+    }
+
+    found.into_iter().vec()
+}
+
+/// The pieces that every position could deal next, or `None` if the pieces can't come from 7-bags.
+/// Positions whose pieces another position could also deal are left out.
+#[must_use]
+pub fn continuation(positions: &[BagPosition], len: usize) -> Option<Pattern> {
+    let remaining = positions.iter().map(BagPosition::remaining).unique().vec();
+    // a continuation only has every queue of another one when the current bags end at the same place,
+    // and all the pieces left in the other bag are left in this one
+    let within = |a: &BTreeSet<Piece>, b: &BTreeSet<Piece>| {
+        a.len().min(len) == b.len().min(len) && a.is_subset(b)
+    };
+
+    let mut alternatives = positions
+        .iter()
+        .filter(|x| {
+            let r = x.remaining();
+            !remaining.iter().any(|o| *o != r && within(&r, o))
+        })
+        .map(|x| x.continuation(len))
+        .unique_by(ToString::to_string)
+        .vec();
+
+    match alternatives.len() {
+        0 => None,
+        1 => alternatives.pop(),
+        _ => Some(Pattern {
+            parts: vec![Part::Or(alternatives)],
+        }),
+    }
+}
+
+/// Deals the known pieces into bags one bag at a time.
+/// Dealt pieces are numbered from the start of the first bag, so the first `offset` are unknown,
+/// the next `used` are the used pieces in any order, and the rest are the queue in order.
+struct Search<'a> {
+    offset: usize,
+    used: usize,
+    queue: &'a [Piece],
+}
+
+impl Search<'_> {
+    fn end(&self) -> usize {
+        self.offset + self.used + self.queue.len()
+    }
+
+    /// Fills bag `bag` and the ones after it, calling `found` with the dealt pieces of the current bag
+    /// for every way to do it.
+    fn assign(&self, bag: usize, counts: &mut [usize; 7], found: &mut impl FnMut(BTreeSet<Piece>)) {
+        let start = bag * BAG.len();
+        if start >= self.end() {
+            if counts.iter().all(|&x| x == 0) {
+                found(BTreeSet::new());
+            }
+            return;
+        }
+
+        let end = (start + BAG.len()).min(self.end());
+        let unknown = (start..end).filter(|&i| i < self.offset).count();
+        let used = (start..end)
+            .filter(|&i| i >= self.offset && i < self.offset + self.used)
+            .count();
+        let visible = (start..end)
+            .filter(|&i| i >= self.offset + self.used)
+            .map(|i| self.queue[i - self.offset - self.used])
+            .vec();
+        if !visible.iter().all_unique() {
+            return;
+        }
+
+        let available = (0..BAG.len())
+            .filter(|&i| counts[i] > 0 && !visible.contains(&BAG[i]))
+            .vec();
+        let is_current = end == self.end() && end - start < BAG.len();
+        for chosen in available.into_iter().combinations(used) {
+            for &i in &chosen {
+                counts[i] -= 1;
+            }
+
+            let dealt: BTreeSet<Piece> = chosen
+                .iter()
+                .map(|&i| BAG[i])
+                .chain(visible.iter().copied())
+                .collect();
+            if is_current {
+                // this bag is still being dealt, so which pieces the unknown ones were matters
+                let free = BAG.into_iter().filter(|x| !dealt.contains(x)).vec();
+                for unknowns in free.into_iter().combinations(unknown) {
+                    if counts.iter().all(|&x| x == 0) {
+                        found(dealt.iter().copied().chain(unknowns).collect());
+                    }
+                }
+            } else {
+                self.assign(bag + 1, counts, found);
+            }
+
+            for &i in &chosen {
+                counts[i] += 1;
+            }
+        }
+    }
+}
@@ -5,7 +5,10 @@ use fumen::Fumen;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
-use crate::{bits::Bits, grid::Grid, piece::Piece, placement::Placement, traits::CollectVec};
+use crate::{
+    bits::Bits, grid::Grid, piece::Piece, placement::Placement, text::parse_error,
+    traits::CollectVec,
+};
 
 #[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Board {
@@ -15,8 +18,7 @@ pub struct Board {
 }
 
 impl Board {
-    #[must_use]
-    pub fn fumen(&self) -> Fumen {
+    pub fn fumen(&self) -> anyhow::Result<Fumen> {
         self.grid().fumen()
     }
 
@@ -38,16 +40,15 @@ impl Board {
         self.data[y][x] = p;
     }
 
-    #[must_use]
-    pub fn fumen_page(&self) -> fumen::Page {
-        self.fumen().pages[0].clone()
+    pub fn fumen_page(&self) -> anyhow::Result<fumen::Page> {
+        Ok(self.fumen()?.pages[0].clone())
     }
 
-    pub fn new(t: impl Display) -> Self {
+    pub fn new(t: impl Display) -> Result<Self, String> {
         let s = t.to_string();
 
-        let x = parse::parser().parse(&s).unwrap();
-        x
+        let parsed = parse::parser().parse(&s);
+        parsed.into_result().map_err(|e| parse_error(&s, &e))
     }
 
     #[must_use]
@@ -1,8 +1,10 @@
 use std::{ops::Deref, str::FromStr};
 
-use fumen::Fumen;
-
-use crate::{fumen::fumen_to_grid, grid::Grid};
+use crate::{
+    field::field_to_grid,
+    fumen::{decode_fumen, fumen_to_grid},
+    grid::Grid,
+};
 
 /// Argument type for either a `Fumen` or a direct `Grid`.
 #[derive(Clone, Debug)]
@@ -24,16 +26,22 @@ impl Tetfu {
 impl FromStr for Tetfu {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("expected a fumen or a grid, found nothing".to_string());
+        }
+
         if s == "_" {
             return Ok(Self(Grid::empty(10, 23)));
         }
 
-        if s[1..].starts_with("115@") {
-            Ok(Self(fumen_to_grid(
-                &Fumen::decode(s).map_err(|x| format!("{x}"))?,
-            )))
+        // grids never contain `@`, while every fumen has one after its version
+        if s.contains('@') {
+            Ok(Self(fumen_to_grid(&decode_fumen(s)?)))
+        } else if s.contains('\n') {
+            Ok(Self(field_to_grid(s)?))
         } else {
-            Ok(Self(Grid::new(s)))
+            Ok(Self(Grid::new(s)?))
         }
     }
 }
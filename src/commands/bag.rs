@@ -0,0 +1,50 @@
+use std::fmt::Write as _;
+
+use itertools::Itertools;
+
+use crate::{
+    bag::{continuation, positions, used_pieces},
+    board_parser::Tetfu,
+    pattern::Queue,
+    piece::Piece,
+    program::Sfce,
+};
+
+impl Sfce {
+    pub fn bag_command(
+        &mut self,
+        tetfu: &Tetfu,
+        queue: &Queue,
+        hold: Option<Piece>,
+        length: usize,
+        offset: Option<usize>,
+    ) -> anyhow::Result<()> {
+        let hold = match (queue.hold(), hold) {
+            (Some(_), Some(_)) => {
+                anyhow::bail!("the queue already has a held piece, so --hold can't be given")
+            }
+            (a, b) => a.or(b),
+        };
+
+        let mut lines = vec![];
+        for board in self.selected_pages(tetfu)? {
+            let mut used = used_pieces(&board);
+            used.extend(hold);
+
+            let mut found = positions(&used, queue.pieces());
+            found.retain(|x| offset.is_none_or(|o| x.offset == o));
+            let line = match continuation(&found, length) {
+                Some(p) if self.program.args.raw => p.to_string(),
+                Some(p) => {
+                    let offsets = found.iter().map(|x| x.offset).unique().join(", ");
+                    format!("{p} (bag offsets {offsets})")
+                }
+                None => "not possible with 7-bag".to_string(),
+            };
+            lines.push(self.page_comment(&board, line));
+        }
+
+        write!(self.buf, "{}", lines.join("\n"))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use clap::Parser;
+
+use crate::program::{Sfce, SfceCommand};
+
+/// A single line of a batch, which is a command without the global options.
+#[derive(clap::Parser, Clone, Debug)]
+#[command(no_binary_name = true, disable_help_flag = true)]
+struct BatchLine {
+    #[command(subcommand)]
+    sub: SfceCommand,
+}
+
+impl Sfce {
+    pub fn batch_command(&mut self, commands: &str) -> anyhow::Result<()> {
+        let mut records = vec![];
+        for line in commands
+            .lines()
+            .map(str::trim)
+            .filter(|x| !x.is_empty() && !x.starts_with('#'))
+        {
+            // commands like `fumen encode` change the options, which shouldn't leak into the next line
+            let args = self.program.args.clone();
+            self.buf.clear();
+
+            let result = BatchLine::try_parse_from(split_words(line))
+                .map_err(|x| {
+                    let e = x.render().to_string();
+                    anyhow::anyhow!(
+                        "{}",
+                        e.lines()
+                            .next()
+                            .unwrap_or_default()
+                            .trim_start_matches("error: ")
+                    )
+                })
+                .and_then(|x| match x.sub {
+                    SfceCommand::Batch { .. } => anyhow::bail!("batches cannot be nested"),
+                    c => self.run_command(c),
+                });
+
+            records.push(match result {
+                Ok(()) => self.buf.trim_end().to_string(),
+                Err(e) => format!("error: {e}"),
+            });
+            self.program.args = args;
+        }
+
+        self.buf = records.join(&self.program.args.batch_sep);
+        Ok(())
+    }
+}
+
+/// Splits a line into words on whitespace, keeping anything inside quotes together.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut quote = None;
+    let mut in_word = false;
+
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if in_word {
+        words.push(word);
+    }
+
+    words
+}
@@ -0,0 +1,89 @@
+use std::{collections::HashSet, fmt::Write};
+
+use itertools::Itertools;
+
+use crate::{
+    board::Board,
+    db::SolutionDb,
+    placement::Placement,
+    program::{DbCli, Sfce},
+    traits::CollectVec,
+};
+
+impl Sfce {
+    pub fn db_command(&mut self, d: DbCli) -> anyhow::Result<()> {
+        match d {
+            DbCli::Query {
+                db,
+                tetfu,
+                pattern,
+                line_clears,
+                first,
+                last,
+                count,
+            } => {
+                let store = SolutionDb::open(&db)?;
+                let boards = tetfu
+                    .map(|t| self.selected_pages(&t.contents()))
+                    .transpose()?
+                    .map(|pages| pages.iter().map(Board::fast).collect::<HashSet<_>>());
+                let queues =
+                    pattern.map(|p| p.contents().queues().into_iter().collect::<HashSet<_>>());
+
+                let found = store
+                    .solutions
+                    .iter()
+                    .filter(|s| boards.as_ref().is_none_or(|b| b.contains(&s.board.fast())))
+                    .filter(|s| queues.as_ref().is_none_or(|q| q.contains(&s.queue)))
+                    .filter(|s| line_clears.contains(&s.line_clears()))
+                    .filter(|s| {
+                        first.is_none_or(|p| s.placements.first().map(Placement::piece) == Some(p))
+                    })
+                    .filter(|s| {
+                        last.is_none_or(|p| s.placements.last().map(Placement::piece) == Some(p))
+                    })
+                    .vec();
+
+                if count {
+                    write!(self.buf, "{}", found.len())?;
+                    return Ok(());
+                }
+                if found.is_empty() {
+                    anyhow::bail!(
+                        "no solutions match, out of {} recorded",
+                        store.solutions.len()
+                    );
+                }
+
+                eprintln!(
+                    "--> {} of {} recorded solutions match",
+                    found.len(),
+                    store.solutions.len()
+                );
+                if self.program.args.raw {
+                    let lines = found
+                        .iter()
+                        .map(|s| {
+                            format!(
+                                "{} -> {}: {}",
+                                s.queue,
+                                s.played,
+                                s.placements.iter().join(";")
+                            )
+                        })
+                        .join("\n");
+                    write!(self.buf, "{lines}")?;
+                } else {
+                    self.write_moves(
+                        &found
+                            .into_iter()
+                            .map(|s| (s.placements.clone(), s.result.clone()))
+                            .vec(),
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
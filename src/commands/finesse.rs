@@ -18,14 +18,20 @@ impl Sfce {
         y: usize,
         rotation: Rotation,
     ) -> anyhow::Result<()> {
-        let b = self.resize(tetfu.grid()).page();
         let p = Placement::new(piece, x, y, rotation);
-        let ks = p.finesse(&b, b.spawn(), self.handling());
-        if let Some(k) = ks {
-            write!(self.buf, "{}", k.iter().join(","))?;
-        } else {
-            anyhow::bail!("no finesse found")
+        let mut lines = vec![];
+        for b in self.selected_pages(tetfu)? {
+            let ks = p.finesse(&b, b.spawn(), self.handling());
+            if let Some(k) = ks {
+                lines.push(self.page_comment(&b, k.iter().join(",")));
+            } else if self.program.args.all_pages {
+                lines.push(self.page_comment(&b, "no finesse found"));
+            } else {
+                anyhow::bail!("no finesse found")
+            }
         }
+
+        write!(self.buf, "{}", lines.join("\n"))?;
         Ok(())
     }
 }
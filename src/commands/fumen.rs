@@ -2,6 +2,7 @@ use std::{fmt::Write, str::FromStr};
 
 use crate::{
     board_parser::Tetfu,
+    field::grid_to_field,
     grid::Grid,
     program::{FumenCli, Sfce},
 };
@@ -13,10 +14,15 @@ impl Sfce {
                 if self.program.args.link_type.is_none() {
                     self.program.args.link_type = Some('v');
                 }
-                self.tetfu(&Grid::new(grid.contents().grid()))
+                self.tetfu(&grid.contents().grid())?
             })?,
-            FumenCli::Decode { fumen } => {
-                writeln!(self.buf, "{}", self.resize(fumen.contents().grid()))?;
+            FumenCli::Decode { fumen, field } => {
+                let g = self.resize(fumen.contents().grid());
+                if field {
+                    writeln!(self.buf, "{}", grid_to_field(&g))?;
+                } else {
+                    writeln!(self.buf, "{g}")?;
+                }
             }
 
             FumenCli::Glue { fumen } => {
@@ -35,7 +41,7 @@ impl Sfce {
                     }
                 }
 
-                writeln!(self.buf, "{}", self.tetfu(&fum))?;
+                writeln!(self.buf, "{}", self.tetfu(&fum)?)?;
             }
 
             FumenCli::Optimize { .. } => {
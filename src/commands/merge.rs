@@ -0,0 +1,17 @@
+use crate::{program::Sfce, shard::ShardResult};
+
+impl Sfce {
+    pub fn merge_command(&mut self, files: &[String]) -> anyhow::Result<()> {
+        let results = files
+            .iter()
+            .map(|x| ShardResult::read(x))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let merged = ShardResult::merge(results)?;
+        eprintln!(
+            "--> {}/{} queues have a solution",
+            merged.solved, merged.queues
+        );
+
+        self.write_moves(&merged.moves)
+    }
+}
@@ -1,3 +1,4 @@
+pub mod batch;
 pub mod fumen;
 pub mod pattern;
 pub mod test;
@@ -7,3 +8,8 @@ pub mod edit;
 pub mod send;
 pub mod finesse;
 pub mod place;
+pub mod bag;
+pub mod merge;
+pub mod db;
+pub mod stats;
+pub mod tile;
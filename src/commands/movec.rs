@@ -1,46 +1,186 @@
-use std::{
-    fmt::Write as _,
-    sync::{Arc, Mutex},
-};
+use std::{fmt::Write as _, sync::Arc};
 
+use dashmap::DashMap;
 use itertools::Itertools;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
 use crate::{
-    board::Board, board_parser::Tetfu, grid::Grid, pattern::Pattern, piece::Piece,
-    placement::Placement, program::Sfce, ranged::Ranged, traits::FullyDedup,
+    bits::Bits,
+    board::Board,
+    board_parser::Tetfu,
+    db::{Solution, SolutionDb},
+    eval::Ranking,
+    grid::Grid,
+    pattern::{HoldStep, Iter, Pattern, Queue},
+    pc::PerfectClear,
+    piece::Piece,
+    placement::Placement,
+    program::Sfce,
+    ranged::Ranged,
+    rng::Rng,
+    shard::{Shard, ShardResult},
+    traits::{CollectVec, FullyDedup},
 };
 
 impl Sfce {
+    #[allow(clippy::too_many_arguments, clippy::too_many_lines)]
     pub fn move_command(
         &mut self,
         tetfu: Tetfu,
         pattern: Pattern,
         total_line_clears: Ranged<usize>,
         continuous_line_clears: Ranged<usize>,
+        sample: Option<usize>,
+        seed: Option<u64>,
+        shard: Option<Shard>,
+        shard_file: Option<String>,
+        db: Option<String>,
+        ranking: Option<Ranking>,
+        pc: Option<&PerfectClear>,
     ) -> anyhow::Result<()> {
-        let b = self.resize(tetfu.grid()).page();
-        let p = pattern.queues();
-        let m = Arc::new(Mutex::new(vec![]));
-        p.into_par_iter().for_each(|q| {
-            self.hold_queues(&q).into_par_iter().for_each(|h| {
-                // println!("{h}");
-                self.all_placements_of_queue(&b, h.pieces(), continuous_line_clears)
-                    .into_par_iter()
-                    .for_each(|p| {
-                        
-                        let mv = b.with_many_placements(&p).with_comment(format!("{q} -> {h}"));
-                        // println!("{p:?} {mv}");
-                        if total_line_clears.contains(&mv.line_clears())
-                            && self.is_many_doable(&b, &p)
-                        {
-                            m.lock().unwrap().push((p, mv))
-                        }
-                    })
-            });
-        });
-
-        if m.lock().unwrap().is_empty() {
+        let seed = seed.unwrap_or_else(Rng::random_seed);
+        let p = match (sample, shard) {
+            (Some(n), _) => pattern.samples(n, &mut Rng::new(seed)).map_err(anyhow::Error::msg)?,
+            (None, Some(s)) => {
+                let queues = Iter::new(&pattern).map_err(anyhow::Error::msg)?;
+                let total = pattern.count().map_err(anyhow::Error::msg)?;
+                queues.range(s.range(total)).vec()
+            }
+            (None, None) => pattern.queues(),
+        };
+        let mut m = vec![];
+        let mut solved = 0;
+        let mut solutions = vec![];
+        let pages = self.selected_pages(&tetfu)?;
+        for b in &pages {
+            let table = Transpositions::default();
+            // collecting keeps the queues in pattern order no matter which thread finishes first
+            let results: Vec<Vec<(Vec<Placement>, Board, Queue)>> = p
+                .par_iter()
+                .map(|q| {
+                    let mut found = self
+                        .all_placements_with_hold(
+                            b,
+                            q,
+                            continuous_line_clears,
+                            pc,
+                            &table,
+                        )
+                        .into_par_iter()
+                        .filter_map(|(p, h)| {
+                            let mv = b
+                                .with_many_placements(&p)
+                                .with_comment(self.page_comment(b, format!("{q} -> {h}")));
+                            (total_line_clears.contains(&mv.line_clears())
+                                && pc.is_none_or(|pc| pc.is_cleared(&mv.fast())))
+                            .then_some((p, mv, h))
+                        })
+                        .collect::<Vec<_>>();
+                    found.sort_by(|x, y| x.0.cmp(&y.0));
+                    found
+                })
+                .collect();
+
+            solved += results.iter().filter(|x| !x.is_empty()).count();
+            for (q, found) in p.iter().zip(results) {
+                for (placements, result, played) in found {
+                    if db.is_some() {
+                        solutions.push(Solution {
+                            board: b.clone(),
+                            queue: q.clone(),
+                            played,
+                            placements: placements.clone(),
+                            result: result.clone(),
+                        });
+                    }
+                    m.push((placements, result));
+                }
+            }
+        }
+
+        if let Some(path) = db {
+            let mut store = SolutionDb::open(&path)?;
+            let added = store.insert(solutions);
+            eprintln!(
+                "--> added {added} new solutions to {path}, which has {} in total",
+                store.solutions.len()
+            );
+            store.write(&path)?;
+        }
+
+        if sample.is_some() {
+            let trials = p.len() * pages.len();
+            let (p, lo, hi) = wilson_interval(solved, trials);
+            eprintln!(
+                "--> {solved}/{trials} sampled queues have a solution, {:.2}% (95% CI {:.2}%..{:.2}%, seed {seed})",
+                100.0 * p,
+                100.0 * lo,
+                100.0 * hi,
+            );
+            // the estimate is the answer, even when none of the sampled queues has a solution
+            if m.is_empty() {
+                return Ok(());
+            }
+        }
+
+        if let Some(shard) = shard {
+            let path = shard_file.unwrap_or_else(|| shard.default_path());
+            let result = ShardResult {
+                shard,
+                run: self.search_fingerprint(
+                    &pattern,
+                    &pages,
+                    total_line_clears,
+                    continuous_line_clears,
+                    pc,
+                ),
+                queues: p.len() * pages.len(),
+                solved,
+                moves: m,
+            };
+            result.write(&path)?;
+            write!(
+                self.buf,
+                "wrote {} results of {} queues for shard {shard} to {path}",
+                result.moves.len(),
+                result.queues
+            )?;
+            return Ok(());
+        }
+
+        if let Some(r) = ranking {
+            let unique = m.into_iter().fully_dedup_by_key(|x| x.1.to_string()).vec();
+            m = r.apply(unique, |x| x.1.fast());
+        }
+
+        self.write_moves(&m)
+    }
+
+    /// Everything that changes the results of `move`, so that `merge` only combines shards of the same search.
+    fn search_fingerprint(
+        &self,
+        pattern: &Pattern,
+        pages: &[Board],
+        total_line_clears: Ranged<usize>,
+        continuous_line_clears: Ranged<usize>,
+        pc: Option<&PerfectClear>,
+    ) -> String {
+        let args = &self.program.args;
+        [
+            format!("pattern {pattern}"),
+            format!("boards {}", pages.iter().join(";")),
+            format!("line clears {total_line_clears:?} {continuous_line_clears:?}"),
+            format!("hold {} {:?}", args.no_hold, args.max_holds),
+            format!("{:?} margin {}", args.handling, args.board_margin),
+            format!("comments {}", !args.no_comments),
+            format!("pc {pc:?}"),
+        ]
+        .join("\n")
+    }
+
+    /// Writes the boards that `move` found, or their placements when the output is raw.
+    pub fn write_moves(&mut self, m: &[(Vec<Placement>, Board)]) -> anyhow::Result<()> {
+        if m.is_empty() {
             anyhow::bail!("no placements found");
         }
 
@@ -49,16 +189,14 @@ impl Sfce {
                 self.buf,
                 "{}",
                 self.tetfu(&Grid::from_pages(
-                    m.lock().unwrap().iter().map(|x| x.1.clone()).fully_dedup()
-                ))
+                    m.iter().map(|x| x.1.clone()).fully_dedup()
+                ))?
             )?;
         } else {
             write!(
                 self.buf,
                 "{}",
-                m.lock()
-                    .unwrap()
-                    .iter()
+                m.iter()
                     .fully_dedup_by_key(|x| x.1.to_string())
                     .map(|x| x.0.iter().map(|x| x.to_string()).join(";"))
                     .join("\n")
@@ -101,43 +239,43 @@ impl Sfce {
         true
     }
 
-    pub fn all_placements_of_queue(
+    /// Every way to place all of `queue` on `board`, along with the order the pieces were placed in.
+    /// Hold is searched as part of the state, and every state's results are kept in `table`,
+    /// so states reached through different holds or different queues are only explored once.
+    /// Ways that end on the same board with the pieces in the same order are only given once.
+    #[must_use]
+    pub fn all_placements_with_hold(
         &self,
         board: &Board,
-        queue: &[Piece],
+        queue: &Queue,
         cls: Ranged<usize>,
-    ) -> Vec<Vec<Placement>> {
-        if queue.is_empty() {
-            return vec![vec![]];
-        }
+        pc: Option<&PerfectClear>,
+        table: &Transpositions,
+    ) -> Vec<(Vec<Placement>, Queue)> {
+        let max_holds = if self.program.args.no_hold {
+            Some(0)
+        } else {
+            self.program.args.max_holds
+        };
+        let search = HoldSearch {
+            sfce: self,
+            queue,
+            cls,
+            pc,
+            table,
+        };
 
-        // i can haz optimizationburger?
-        if queue.len() == 1 {
-            return self
-                .all_placements_of_piece(board, queue[0], cls)
-                .iter()
-                .map(|x| vec![*x])
-                .collect();
+        let pieces = queue.iter().copied().chain(queue.hold()).vec();
+        if pc.is_some_and(|pc| !pc.can_start(&board.fast(), &pieces)) {
+            return vec![];
         }
 
-        let piece = queue[0];
-        let remaining_queue = &queue[1..];
-        let placements = self.all_placements_of_piece(board, piece, cls);
-
-        placements
+        let mut found = vec![];
+        search.completions(board, 0, queue.hold(), max_holds, &mut vec![], &mut found);
+        found
             .into_iter()
-            .map(|p| {
-                let mut s = board.clone();
-                s.place(p);
-
-                let sub_placements = self.all_placements_of_queue(&s, remaining_queue, cls);
-
-                sub_placements.into_iter().map(move |mut sr| {
-                    sr.insert(0, p);
-                    sr
-                })
-            })
-            .flatten()
+            .fully_dedup_by_key(|x| (board.with_many_placements(&x.0).rows().clone(), x.1.clone()))
+            .map(|(p, order)| (p, order.into_iter().collect()))
             .collect()
     }
 
@@ -158,3 +296,172 @@ impl Sfce {
             .collect()
     }
 }
+
+/// The proportion of `successes` out of `trials`, and its 95% Wilson score interval, which stays sensible near 0% and 100%.
+#[allow(clippy::cast_precision_loss)]
+fn wilson_interval(successes: usize, trials: usize) -> (f64, f64, f64) {
+    if trials == 0 {
+        return (0.0, 0.0, 1.0);
+    }
+
+    let z = 1.96_f64;
+    let n = trials as f64;
+    let p = successes as f64 / n;
+    let denom = 1.0 + z * z / n;
+    let center = (p + z * z / (2.0 * n)) / denom;
+    let half = z * (p * (1.0 - p) / n + z * z / (4.0 * n * n)).sqrt() / denom;
+
+    (p, (center - half).max(0.0), (center + half).min(1.0))
+}
+
+/// The placements that finish a queue from some state, and the order of their pieces.
+type Completion = (Vec<Placement>, Vec<Piece>);
+
+/// A placement out of some state, and the step through the queue that it makes.
+type Edge = (Placement, HoldStep);
+
+/// The field, the pieces left in the queue, the held piece and how many more holds are allowed.
+type HoldState = (Bits, Vec<Piece>, Option<Piece>, Option<usize>);
+
+/// Results of a `move` search that are shared between all of its queues and threads.
+#[derive(Default)]
+pub struct Transpositions {
+    /// The doable placements of a piece on a field.
+    placements: DashMap<(Bits, Piece), Arc<Vec<Placement>>>,
+    /// The placements out of each state that can still finish the queue,
+    /// which the ways to finish it are rebuilt from instead of keeping all of them.
+    edges: DashMap<HoldState, Arc<Vec<Edge>>>,
+}
+
+/// A depth-first search over the states of placing a queue with hold.
+struct HoldSearch<'a> {
+    sfce: &'a Sfce,
+    queue: &'a Queue,
+    cls: Ranged<usize>,
+    /// Stops searching fields the perfect clear can't be finished from.
+    pc: Option<&'a PerfectClear>,
+    table: &'a Transpositions,
+}
+
+impl HoldSearch<'_> {
+    fn placements(&self, board: &Board, field: &Bits, piece: Piece) -> Arc<Vec<Placement>> {
+        let key = (field.clone(), piece);
+        if let Some(x) = self.table.placements.get(&key) {
+            return x.clone();
+        }
+
+        let placements = Arc::new(
+            self.sfce
+                .all_placements_of_piece(board, piece, self.cls)
+                .into_iter()
+                // checking that a placement is doable is slow, and a perfect clear never goes above its top
+                .filter(|&p| self.pc.is_none_or(|pc| pc.fits(board, p)))
+                .filter(|p| p.is_doable(board, board.spawn(), self.sfce.handling()))
+                .vec(),
+        );
+        self.table.placements.insert(key, placements.clone());
+        placements
+    }
+
+    fn is_finished(&self, taken: usize, hold: Option<Piece>) -> bool {
+        taken == self.queue.len() && hold.is_none()
+    }
+
+    /// The placements out of a state that lead to a state where the queue is finished.
+    fn edges(
+        &self,
+        board: &Board,
+        taken: usize,
+        hold: Option<Piece>,
+        holds_left: Option<usize>,
+    ) -> Arc<Vec<Edge>> {
+        let field = board.fast();
+        let key = (
+            field.clone(),
+            self.queue.pieces()[taken..].to_vec(),
+            hold,
+            holds_left,
+        );
+        if let Some(x) = self.table.edges.get(&key) {
+            return x.clone();
+        }
+
+        if let Some(pc) = self.pc {
+            let remaining = key.1.iter().copied().chain(hold).vec();
+            if !pc.can_finish(&field, &remaining) {
+                return Arc::default();
+            }
+        }
+
+        let mut found = vec![];
+        let can_hold = holds_left.is_none_or(|x| x > 0);
+        // placing the held piece when it is the same as the current one leads to the same states
+        for step in self
+            .queue
+            .next_steps(taken, hold, can_hold)
+            .into_iter()
+            .unique_by(|x| (x.piece, x.taken, x.hold))
+        {
+            let holds_left = holds_left.map(|x| x - usize::from(step.uses_hold));
+            for &p in self.placements(board, &field, step.piece).iter() {
+                if self.is_finished(step.taken, step.hold)
+                    || !self
+                        .edges(&board.with_placement(p), step.taken, step.hold, holds_left)
+                        .is_empty()
+                {
+                    found.push((p, step));
+                }
+            }
+        }
+
+        let found = Arc::new(found);
+        self.table.edges.insert(key, found.clone());
+        found
+    }
+
+    /// Adds every way to finish the queue from a state to `found`, following the edges out of it.
+    fn completions(
+        &self,
+        board: &Board,
+        taken: usize,
+        hold: Option<Piece>,
+        holds_left: Option<usize>,
+        path: &mut Vec<Placement>,
+        found: &mut Vec<Completion>,
+    ) {
+        if self.is_finished(taken, hold) {
+            found.push((path.clone(), path.iter().map(Placement::piece).vec()));
+            return;
+        }
+
+        for &(p, step) in self.edges(board, taken, hold, holds_left).iter() {
+            let holds_left = holds_left.map(|x| x - usize::from(step.uses_hold));
+            path.push(p);
+            let next = board.with_placement(p);
+            self.completions(&next, step.taken, step.hold, holds_left, path, found);
+            path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wilson_interval;
+
+    fn assert_close(found: (f64, f64, f64), expected: (f64, f64, f64)) {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-4;
+        assert!(
+            close(found.0, expected.0) && close(found.1, expected.1) && close(found.2, expected.2),
+            "{found:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn wilson_interval_stays_within_0_and_1() {
+        assert_close(wilson_interval(0, 0), (0.0, 0.0, 1.0));
+        assert_close(wilson_interval(0, 3), (0.0, 0.0, 0.5615));
+        assert_close(wilson_interval(3, 3), (1.0, 0.4385, 1.0));
+        assert_close(wilson_interval(5, 10), (0.5, 0.2366, 0.7634));
+        assert_close(wilson_interval(90, 100), (0.9, 0.8256, 0.9448));
+    }
+}
@@ -1,6 +1,10 @@
-use std::fmt::Write;
+use std::{fmt::Write, str::FromStr};
 
-use crate::program::{PatternCli, Sfce};
+use crate::{
+    pattern::{Pattern, Queue},
+    program::{PatternCli, Sfce},
+    rng::Rng,
+};
 
 impl Sfce {
     pub fn pattern_command(&mut self, l: PatternCli) -> anyhow::Result<()> {
@@ -8,18 +12,17 @@ impl Sfce {
             PatternCli::Expand { pattern } => {
                 // println!("{:?}", pat);
                 let list = pattern.contents();
-                let mut i = 0;
-                for q in list {
+                let total = list.count().map_err(anyhow::Error::msg)?;
+                for (i, q) in list.into_iter().enumerate() {
                     if i % self.program.args.pw == 0 {
                         writeln!(self.buf)?;
                     }
                     write!(self.buf, "{q} ")?;
-                    i += 1;
                 }
 
                 writeln!(self.buf)?;
 
-                writeln!(self.buf, "{i} queues total")?;
+                writeln!(self.buf, "{total} queues total")?;
             }
 
             PatternCli::Hold { pattern } => {
@@ -28,7 +31,7 @@ impl Sfce {
                 for q in list {
                     write!(self.buf, "\x1b[1m{q}\x1b[0m")?;
 
-                    for (i, hq) in q.hold_queues().into_iter().enumerate() {
+                    for (i, hq) in self.hold_queues(&q).into_iter().enumerate() {
                         if i % self.program.args.pw == 0 {
                             writeln!(self.buf)?;
                         }
@@ -38,8 +41,118 @@ impl Sfce {
                     writeln!(self.buf, "\n")?;
                 }
             }
+
+            PatternCli::Union { pair } => {
+                let list = pair.first.union(&pair.second, pair.hold);
+                self.write_queue_set(&list, pair.expr)?;
+            }
+
+            PatternCli::Intersect { pair } => {
+                let list = pair.first.intersection(&pair.second, pair.hold);
+                self.write_queue_set(&list, pair.expr)?;
+            }
+
+            PatternCli::Diff { pair } => {
+                let list = pair.first.difference(&pair.second, pair.hold);
+                self.write_queue_set(&list, pair.expr)?;
+            }
+
+            PatternCli::Equal { pair } => {
+                let only_first = pair.first.difference(&pair.second, pair.hold).len();
+                let only_second = pair.second.difference(&pair.first, pair.hold).len();
+                if only_first == 0 && only_second == 0 {
+                    write!(self.buf, "true")?;
+                } else {
+                    write!(
+                        self.buf,
+                        "false: {only_first} queues only in the first, {only_second} only in the second"
+                    )?;
+                }
+            }
+
+            PatternCli::Compress { queues } => {
+                let list = queues
+                    .lines()
+                    .flat_map(|x| x.split('#').next().unwrap_or_default().split([' ', '\t', ',', '|']))
+                    .filter(|x| !x.is_empty())
+                    .map(Queue::from_str)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(anyhow::Error::msg)?;
+                self.write_queue_set(&list, true)?;
+            }
+
+            PatternCli::Random {
+                randomizer,
+                length,
+                skip,
+                amount,
+                seed,
+            } => {
+                let seed = seed_or_random(seed);
+                let mut rng = Rng::new(seed);
+                for i in 0..amount {
+                    if i != 0 && i % self.program.args.pw == 0 {
+                        writeln!(self.buf)?;
+                    }
+                    let mut stream = randomizer.stream(Rng::new(rng.next_u64()));
+                    stream.by_ref().take(skip).for_each(drop);
+                    write!(self.buf, "{} ", stream.queue(length))?;
+                }
+            }
+
+            PatternCli::Count { pattern } => {
+                let count = pattern.contents().count().map_err(anyhow::Error::msg)?;
+                write!(self.buf, "{count}")?;
+            }
+
+            PatternCli::Sample {
+                pattern,
+                amount,
+                seed,
+            } => {
+                let seed = seed_or_random(seed);
+                let list = pattern.contents().samples(amount, &mut Rng::new(seed));
+                for (i, q) in list.map_err(anyhow::Error::msg)?.iter().enumerate() {
+                    if i != 0 && i % self.program.args.pw == 0 {
+                        writeln!(self.buf)?;
+                    }
+                    write!(self.buf, "{q} ")?;
+                }
+            }
         }
 
         Ok(())
     }
+
+    /// Writes a set of queues the same way `pattern expand` does, or as one pattern that expands to them.
+    fn write_queue_set(&mut self, list: &[Queue], expr: bool) -> anyhow::Result<()> {
+        if expr {
+            match Pattern::from_queues(list.iter().cloned()) {
+                Some(p) => write!(self.buf, "{p}")?,
+                None => anyhow::bail!("no queues to write as a pattern"),
+            }
+            return Ok(());
+        }
+
+        for (i, q) in list.iter().enumerate() {
+            if i % self.program.args.pw == 0 {
+                writeln!(self.buf)?;
+            }
+            write!(self.buf, "{q} ")?;
+        }
+
+        writeln!(self.buf)?;
+
+        writeln!(self.buf, "{} queues total", list.len())?;
+        Ok(())
+    }
+}
+
+/// The given seed, or a random one that is shown so the result can be reproduced.
+fn seed_or_random(seed: Option<u64>) -> u64 {
+    seed.unwrap_or_else(|| {
+        let s = Rng::random_seed();
+        eprintln!("--> seed {s}");
+        s
+    })
 }
@@ -6,15 +6,22 @@ use crate::{
 
 impl Sfce {
     pub fn place(&mut self, tetfu: &Tetfu, piece: Piece, x: usize, y: usize, rotation: Rotation) -> anyhow::Result<()> {
-        let binding = self.resize(tetfu.grid());
-        let mut board = binding.pages().first().cloned().unwrap().to_gray();
-        let p = Placement::new(piece, x, y, rotation);
-        if !board.is_valid_placement(p, true) {
-            anyhow::bail!("invalid placement");
+        let placement = Placement::new(piece, x, y, rotation);
+        let mut grid = Grid::default();
+        for board in self.selected_pages(tetfu)? {
+            let mut board = board.to_gray();
+            if board.is_valid_placement(placement, true) {
+                board.place(placement);
+            } else if self.program.args.all_pages {
+                let comment = self.page_comment(&board, "invalid placement");
+                board.set_comment(comment);
+            } else {
+                anyhow::bail!("invalid placement");
+            }
+            grid.add_page(board);
         }
-        board.place(p);
 
-        writeln!(self.buf, "{}", self.tetfu(&Grid::from_pages([board.clone()])))?;
+        writeln!(self.buf, "{}", self.tetfu(&grid)?)?;
 
         Ok(())
     }
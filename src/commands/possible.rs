@@ -15,17 +15,18 @@ impl Sfce {
         tetfu: &Tetfu,
         piece: Piece,
     ) -> anyhow::Result<()> {
-        let binding = self.resize(tetfu.grid());
-        let board = binding.pages().first().unwrap();
-        let zz = Rotation::iter().map(|x| {
-            (board.clone().to_gray() | board.fast().possible_placements(piece, x).tint(piece)).with_comment(x)
-        });
-        
+        let mut g = Grid::default();
+        for board in self.selected_pages(tetfu)? {
+            g.extend(Rotation::iter().map(|x| {
+                (board.clone().to_gray() | board.fast().possible_placements(piece, x).tint(piece))
+                    .with_comment(self.page_comment(&board, x))
+            }));
+        }
 
         writeln!(
             self.buf,
             "{}",
-            self.tetfu(&Grid::from_pages(zz))
+            self.tetfu(&g)?
         )?;
         Ok(())
     }
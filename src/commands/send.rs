@@ -2,6 +2,7 @@ use std::fmt::Write as _;
 
 use crate::{
     board_parser::Tetfu,
+    grid::Grid,
     input::{Input, Key},
     piece::{Piece, Rotation},
     program::Sfce,
@@ -14,17 +15,22 @@ impl Sfce {
         piece: Piece,
         keys: &[Key],
     ) -> anyhow::Result<()> {
-        let binding = self.resize(tetfu.grid());
-        let board = binding.pages().first().unwrap();
-        let mut i = Input::new(
-            board,
-            piece,
-            board.spawn(),
-            Rotation::North,
-            self.handling(),
-        );
-        let g = i.show_inputs(keys);
-        write!(self.buf, "{}", self.tetfu(&g))?;
+        let mut g = Grid::default();
+        for board in self.selected_pages(tetfu)? {
+            let mut i = Input::new(
+                &board,
+                piece,
+                board.spawn(),
+                Rotation::North,
+                self.handling(),
+            );
+            g.extend(i.show_inputs(keys).0.into_iter().map(|x| {
+                let c = x.comment().clone().unwrap_or_default();
+                x.with_comment(self.page_comment(&board, c))
+            }));
+        }
+
+        write!(self.buf, "{}", self.tetfu(&g)?)?;
         Ok(())
     }
 }
@@ -0,0 +1,83 @@
+use std::fmt::Write as _;
+
+use itertools::Itertools;
+
+use crate::{
+    board_parser::Tetfu,
+    eval::{
+        checkerboard, column_parity, empty_below, heights, overhangs, pc_lines, wells, Metrics,
+    },
+    fumen::FUMEN_HEIGHT,
+    program::Sfce,
+};
+
+impl Sfce {
+    pub fn stats_command(&mut self, tetfu: &Tetfu) -> anyhow::Result<()> {
+        let mut pages = vec![];
+        for board in self.selected_pages(tetfu)? {
+            let mut bits = board.fast();
+            bits.skim();
+            let heights = heights(&bits);
+            let top = heights.iter().copied().max().unwrap_or(0);
+            let metrics = Metrics::of(&bits);
+            // boards are often given only as tall as their stack
+            let pc = pc_lines(&bits, board.total_height().max(FUMEN_HEIGHT));
+
+            // parity only matters for the cells a perfect clear still has to fill
+            let rows = pc.unwrap_or(top);
+            let empty = empty_below(&bits, rows);
+            let (light, dark) = checkerboard(&empty);
+            let (even, odd) = column_parity(&empty);
+            let wells = wells(&heights);
+            let filled = bits.filled_cells().len();
+            let margin = board.intersects_margin();
+
+            let page = if self.program.args.raw {
+                let wells = wells.iter().map(|(x, d)| format!("{x}:{d}")).join(",");
+                let pc = pc.map_or("none".to_string(), |x| x.to_string());
+                format!(
+                    "filled={filled} empty={} rows={rows} light={light} dark={dark} even={even} odd={odd} holes={} overhangs={} wells={wells} height={top} margin={margin} pc={pc}",
+                    empty.len(),
+                    metrics.holes,
+                    overhangs(&bits),
+                )
+            } else {
+                let divisible = if empty.len().is_multiple_of(4) {
+                    "divisible by 4"
+                } else {
+                    "not divisible by 4"
+                };
+                let wells = if wells.is_empty() {
+                    "none".to_string()
+                } else {
+                    wells
+                        .iter()
+                        .map(|(x, d)| format!("x={x} ({d} deep)"))
+                        .join(", ")
+                };
+                let pc = pc.map_or("not possible".to_string(), |x| format!("{x} lines"));
+                [
+                    format!("filled cells: {filled}"),
+                    format!(
+                        "empty cells: {} in the bottom {rows} rows, {divisible}",
+                        empty.len()
+                    ),
+                    format!("checkerboard: {light} light, {dark} dark"),
+                    format!("columns: {even} in even columns, {odd} in odd columns"),
+                    format!("holes: {}", metrics.holes),
+                    format!("overhangs: {}", overhangs(&bits)),
+                    format!("wells: {wells}"),
+                    format!("max height: {top}"),
+                    format!("intersects margin: {}", if margin { "yes" } else { "no" }),
+                    format!("perfect clear: {pc}"),
+                ]
+                .join("\n")
+            };
+            pages.push(self.page_comment(&board, page));
+        }
+
+        let sep = if self.program.args.raw { "\n" } else { "\n\n" };
+        write!(self.buf, "{}", pages.join(sep))?;
+        Ok(())
+    }
+}
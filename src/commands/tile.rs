@@ -0,0 +1,147 @@
+use std::collections::{BTreeMap, HashSet};
+
+use itertools::Itertools;
+
+use crate::{
+    board::Board,
+    board_parser::Tetfu,
+    eval::{empty_below, pc_lines},
+    fumen::FUMEN_HEIGHT,
+    pattern::{Iter, Pattern},
+    placement::Placement,
+    program::Sfce,
+    tile::tilings,
+};
+
+impl Sfce {
+    pub fn tile_command(
+        &mut self,
+        tetfu: &Tetfu,
+        pattern: &Pattern,
+        lines: Option<usize>,
+        limit: Option<usize>,
+        doable: bool,
+    ) -> anyhow::Result<()> {
+        // the order of a queue doesn't matter when gravity is ignored
+        let sets = Iter::new(pattern)
+            .map_err(anyhow::Error::msg)?
+            .map(|q| {
+                q.iter()
+                    .copied()
+                    .chain(q.hold())
+                    .counts()
+                    .into_iter()
+                    .collect::<BTreeMap<_, _>>()
+            })
+            .unique()
+            .collect::<Vec<_>>();
+
+        let mut m = vec![];
+        for mut board in self.selected_pages(tetfu)? {
+            let field = board.fast();
+            let max = board.total_height().max(FUMEN_HEIGHT);
+            let Some(lines) = lines.or_else(|| pc_lines(&field, max)) else {
+                anyhow::bail!(
+                    "the empty cells can't be split into pieces below the top of the playfield"
+                );
+            };
+            // boards are often given only as tall as their stack, so the lines to fill can be above it
+            if board.height() < lines {
+                board.set_height(lines.max(FUMEN_HEIGHT));
+            }
+            let region = empty_below(&field, lines);
+            if !sets
+                .iter()
+                .any(|x| x.values().sum::<usize>() * 4 == region.len())
+            {
+                anyhow::bail!(
+                    "no queue of the pattern has {} pieces to fill the {} empty cells",
+                    region.len() / 4,
+                    region.len()
+                );
+            }
+
+            for pieces in sets
+                .iter()
+                .filter(|x| x.values().sum::<usize>() * 4 == region.len())
+            {
+                let name = pieces
+                    .iter()
+                    .map(|(p, n)| p.to_string().repeat(*n))
+                    .join("");
+                // tilings that turn out not to be doable don't count towards the limit
+                let budget = limit.filter(|_| !doable).map(|x| x.saturating_sub(m.len()));
+                for tiling in tilings(&region, pieces, budget) {
+                    if limit.is_some_and(|x| m.len() >= x) {
+                        break;
+                    }
+
+                    let placements = if doable {
+                        let Some(order) = self.doable_order(&board, &tiling) else {
+                            continue;
+                        };
+                        order
+                    } else {
+                        tiling
+                    };
+
+                    let mut b = board.clone();
+                    for p in &placements {
+                        for (x, y) in p.cells().into_iter().flatten() {
+                            b.set(x, y, p.piece());
+                        }
+                    }
+                    m.push((placements, b.with_comment(self.page_comment(&board, &name))));
+                }
+            }
+        }
+
+        if m.is_empty() {
+            anyhow::bail!("no tilings found");
+        }
+
+        eprintln!("--> {} tilings found", m.len());
+        self.write_moves(&m)
+    }
+
+    /// An order to place `placements` in where each one can be reached after the ones before it,
+    /// checked with the same movement as `move`.
+    #[must_use]
+    pub fn doable_order(&self, board: &Board, placements: &[Placement]) -> Option<Vec<Placement>> {
+        fn search(
+            sfce: &Sfce,
+            board: &Board,
+            left: &[Placement],
+            dead: &mut HashSet<Vec<Placement>>,
+        ) -> Option<Vec<Placement>> {
+            if left.is_empty() {
+                return Some(vec![]);
+            }
+            if dead.contains(left) {
+                return None;
+            }
+
+            for (i, p) in left.iter().enumerate() {
+                if !sfce.is_many_doable(board, &[*p]) {
+                    continue;
+                }
+
+                let rest = left
+                    .iter()
+                    .enumerate()
+                    .filter(|x| x.0 != i)
+                    .map(|x| *x.1)
+                    .collect::<Vec<_>>();
+                if let Some(mut order) = search(sfce, &board.with_placement(*p), &rest, dead) {
+                    order.insert(0, *p);
+                    return Some(order);
+                }
+            }
+
+            dead.insert(left.to_vec());
+            None
+        }
+
+        search(self, board, placements, &mut HashSet::new())
+    }
+}
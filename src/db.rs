@@ -0,0 +1,72 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{board::Board, pattern::Queue, placement::Placement, traits::FullyDedup};
+
+/// One result of `move`, with everything needed to tell whether a later search would find it again.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Solution {
+    /// The board the search started from.
+    pub board: Board,
+    /// The queue that was searched.
+    pub queue: Queue,
+    /// The pieces in the order they were placed, after using hold.
+    pub played: Queue,
+    pub placements: Vec<Placement>,
+    /// The board after every placement, where cleared lines are kept so that they can be counted.
+    pub result: Board,
+}
+
+impl Solution {
+    #[must_use]
+    pub fn line_clears(&self) -> usize {
+        self.result.line_clears()
+    }
+
+    /// The same solution without the page comments of its boards, which don't change what it is.
+    fn without_comments(&self) -> Self {
+        let mut x = self.clone();
+        x.board.comment = None;
+        x.result.comment = None;
+        x
+    }
+}
+
+/// The solutions that have been found so far, kept in a file between runs.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SolutionDb {
+    pub solutions: Vec<Solution>,
+}
+
+impl SolutionDb {
+    /// Reads the database at `path`, or starts an empty one if there is no file there yet.
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        match File::open(path) {
+            Ok(file) => bincode::deserialize_from(BufReader::new(file))
+                .map_err(|e| anyhow::anyhow!("{path} is not a solution database: {e}")),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn write(&self, path: &str) -> anyhow::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(file, self)?;
+        Ok(())
+    }
+
+    /// Adds solutions that aren't in the database yet, ignoring page comments, and returns how many were new.
+    pub fn insert(&mut self, solutions: impl IntoIterator<Item = Solution>) -> usize {
+        let before = self.solutions.len();
+        self.solutions = std::mem::take(&mut self.solutions)
+            .into_iter()
+            .chain(solutions)
+            .fully_dedup_by_key(Solution::without_comments)
+            .collect();
+        self.solutions.len() - before
+    }
+}
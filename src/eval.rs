@@ -0,0 +1,372 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::bits::Bits;
+
+/// Shape measurements of a board, taken after its cleared lines are removed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Empty cells with a filled cell somewhere above them.
+    pub holes: usize,
+    /// Filled cells above the lowest hole of their column.
+    pub covered: usize,
+    /// The sum of the height differences between neighbouring columns.
+    pub bumpiness: usize,
+    /// Changes between filled and empty along each row, counting the walls as filled.
+    pub row_transitions: usize,
+    /// Changes between filled and empty up each column, counting the floor as filled.
+    pub column_transitions: usize,
+    /// The depth of the deepest well.
+    pub well_depth: usize,
+    /// Places a T can drop into pointing down with at least 3 of its corners filled.
+    pub t_slots: usize,
+    /// How many more filled cells are on one colour of a checkerboard than the other.
+    pub parity: usize,
+}
+
+impl Metrics {
+    #[must_use]
+    pub fn of(bits: &Bits) -> Self {
+        let mut bits = bits.clone();
+        bits.skim();
+        let heights = heights(&bits);
+
+        let mut holes = 0;
+        let mut covered = 0;
+        for (x, &h) in heights.iter().enumerate() {
+            holes += (0..h).filter(|&y| !bits.get(x, y)).count();
+            if let Some(lowest) = (0..h).find(|&y| !bits.get(x, y)) {
+                covered += (lowest..h).filter(|&y| bits.get(x, y)).count();
+            }
+        }
+
+        let top = heights.iter().copied().max().unwrap_or(0).cast_signed();
+        let (w, h) = (bits.width.cast_signed(), bits.height.cast_signed());
+        let filled = |x: isize, y: isize| {
+            x < 0 || y < 0 || x >= w || bits.get(x.unsigned_abs(), y.unsigned_abs())
+        };
+
+        let row_transitions = (0..top)
+            .map(|y| {
+                (-1..w)
+                    .filter(|&x| filled(x, y) != filled(x + 1, y))
+                    .count()
+            })
+            .sum();
+        let column_transitions = (0..w)
+            .map(|x| {
+                (-1..h - 1)
+                    .filter(|&y| filled(x, y) != filled(x, y + 1))
+                    .count()
+            })
+            .sum();
+
+        let t_slots = (0..w)
+            .flat_map(|x| (1..h).map(move |y| (x, y)))
+            .filter(|&(x, y)| {
+                let fits = [(x - 1, y), (x, y), (x + 1, y), (x, y - 1)]
+                    .iter()
+                    .all(|&(cx, cy)| cx >= 0 && cx < w && !filled(cx, cy));
+                let corners = [
+                    (x - 1, y - 1),
+                    (x + 1, y - 1),
+                    (x - 1, y + 1),
+                    (x + 1, y + 1),
+                ]
+                .iter()
+                .filter(|&&(cx, cy)| filled(cx, cy))
+                .count();
+                fits && corners >= 3 && filled(x, y - 2)
+            })
+            .count();
+
+        let (light, dark) = checkerboard(&bits.filled_cells().into_iter().collect::<Vec<_>>());
+
+        Self {
+            holes,
+            covered,
+            bumpiness: heights.windows(2).map(|w| w[0].abs_diff(w[1])).sum(),
+            row_transitions,
+            column_transitions,
+            well_depth: wells(&heights).into_iter().map(|w| w.1).max().unwrap_or(0),
+            t_slots,
+            parity: light.abs_diff(dark),
+        }
+    }
+
+    /// The value of one metric, or the weighted score of all of them.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn get(&self, metric: Metric, weights: &Weights) -> f64 {
+        match metric {
+            Metric::Score => weights.score(self),
+            Metric::Holes => self.holes as f64,
+            Metric::Covered => self.covered as f64,
+            Metric::Bumpiness => self.bumpiness as f64,
+            Metric::RowTransitions => self.row_transitions as f64,
+            Metric::ColumnTransitions => self.column_transitions as f64,
+            Metric::WellDepth => self.well_depth as f64,
+            Metric::TSlots => self.t_slots as f64,
+            Metric::Parity => self.parity as f64,
+        }
+    }
+}
+
+/// How tall each column is, up to its highest filled cell.
+#[must_use]
+pub fn heights(bits: &Bits) -> Vec<usize> {
+    (0..bits.width)
+        .map(|x| {
+            (0..bits.height)
+                .rev()
+                .find(|&y| bits.get(x, y))
+                .map_or(0, |y| y + 1)
+        })
+        .collect()
+}
+
+/// The columns lower than both of their neighbours, with how far below the lower neighbour they are.
+/// The walls count as being as tall as the other neighbour.
+#[must_use]
+pub fn wells(heights: &[usize]) -> Vec<(usize, usize)> {
+    (0..heights.len())
+        .filter_map(|x| {
+            let left = x.checked_sub(1).map(|l| heights[l]);
+            let right = heights.get(x + 1).copied();
+            let side = match (left, right) {
+                (Some(l), Some(r)) => l.min(r),
+                (Some(s), None) | (None, Some(s)) => s,
+                (None, None) => return None,
+            };
+            (side > heights[x]).then(|| (x, side - heights[x]))
+        })
+        .collect()
+}
+
+/// Filled cells with an empty cell right below them.
+#[must_use]
+pub fn overhangs(bits: &Bits) -> usize {
+    (0..bits.width)
+        .flat_map(|x| (1..bits.height).map(move |y| (x, y)))
+        .filter(|&(x, y)| bits.get(x, y) && !bits.get(x, y - 1))
+        .count()
+}
+
+/// The fewest lines a perfect clear could use, which must reach the top of the stack
+/// and leave a multiple of 4 empty cells below it, in a playfield `max` rows tall.
+#[must_use]
+pub fn pc_lines(bits: &Bits, max: usize) -> Option<usize> {
+    let top = heights(bits).into_iter().max().unwrap_or(0);
+    (top.max(1)..=max.max(bits.height))
+        .find(|&lines| empty_below(bits, lines).len().is_multiple_of(4))
+}
+
+/// The empty cells in the bottom `lines` rows, where rows above the top of `bits` are empty.
+#[must_use]
+pub fn empty_below(bits: &Bits, lines: usize) -> Vec<(usize, usize)> {
+    (0..lines)
+        .flat_map(|y| (0..bits.width).map(move |x| (x, y)))
+        .filter(|&(x, y)| y >= bits.height || !bits.get(x, y))
+        .collect()
+}
+
+/// How many of `cells` are on the light and dark squares of a checkerboard.
+#[must_use]
+pub fn checkerboard(cells: &[(usize, usize)]) -> (usize, usize) {
+    let light = cells.iter().filter(|(x, y)| (x + y) % 2 == 0).count();
+    (light, cells.len() - light)
+}
+
+/// How many of `cells` are in even and odd columns.
+#[must_use]
+pub fn column_parity(cells: &[(usize, usize)]) -> (usize, usize) {
+    let even = cells.iter().filter(|(x, _)| x % 2 == 0).count();
+    (even, cells.len() - even)
+}
+
+/// One of the metrics, or `score` for all of them weighted together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    Score,
+    Holes,
+    Covered,
+    Bumpiness,
+    RowTransitions,
+    ColumnTransitions,
+    WellDepth,
+    TSlots,
+    Parity,
+}
+
+impl Metric {
+    const ALL: [Self; 9] = [
+        Self::Score,
+        Self::Holes,
+        Self::Covered,
+        Self::Bumpiness,
+        Self::RowTransitions,
+        Self::ColumnTransitions,
+        Self::WellDepth,
+        Self::TSlots,
+        Self::Parity,
+    ];
+
+    /// Whether boards with more of this are better.
+    #[must_use]
+    pub fn higher_is_better(self) -> bool {
+        matches!(self, Self::Score | Self::TSlots)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Score => "score",
+            Self::Holes => "holes",
+            Self::Covered => "covered",
+            Self::Bumpiness => "bumpiness",
+            Self::RowTransitions => "row-transitions",
+            Self::ColumnTransitions => "column-transitions",
+            Self::WellDepth => "well-depth",
+            Self::TSlots => "t-slots",
+            Self::Parity => "parity",
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase().replace('_', "-");
+        Self::ALL
+            .into_iter()
+            .find(|m| m.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown metric `{s}`, expected one of {}",
+                    Self::ALL.map(Self::name).join(", ")
+                )
+            })
+    }
+}
+
+impl Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// How much each metric adds to a board's score. Bad shapes have negative weights.
+///
+/// Written one per line as `holes = -4`, where lines starting with `#` are ignored
+/// and metrics that aren't given keep their default weight.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Weights {
+    pub holes: f64,
+    pub covered: f64,
+    pub bumpiness: f64,
+    pub row_transitions: f64,
+    pub column_transitions: f64,
+    pub well_depth: f64,
+    pub t_slots: f64,
+    pub parity: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            holes: -4.0,
+            covered: -1.0,
+            bumpiness: -0.5,
+            row_transitions: -0.5,
+            column_transitions: -1.0,
+            well_depth: -0.25,
+            t_slots: 1.0,
+            parity: -0.5,
+        }
+    }
+}
+
+impl Weights {
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn score(&self, m: &Metrics) -> f64 {
+        self.holes * m.holes as f64
+            + self.covered * m.covered as f64
+            + self.bumpiness * m.bumpiness as f64
+            + self.row_transitions * m.row_transitions as f64
+            + self.column_transitions * m.column_transitions as f64
+            + self.well_depth * m.well_depth as f64
+            + self.t_slots * m.t_slots as f64
+            + self.parity * m.parity as f64
+    }
+}
+
+impl FromStr for Weights {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = Self::default();
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected `metric = weight`", n + 1))?;
+            let value: f64 = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("line {}: `{}` is not a number", n + 1, value.trim()))?;
+            let weight = match name
+                .trim()
+                .parse::<Metric>()
+                .map_err(|e| format!("line {}: {e}", n + 1))?
+            {
+                Metric::Score => return Err(format!("line {}: `score` has no weight", n + 1)),
+                Metric::Holes => &mut weights.holes,
+                Metric::Covered => &mut weights.covered,
+                Metric::Bumpiness => &mut weights.bumpiness,
+                Metric::RowTransitions => &mut weights.row_transitions,
+                Metric::ColumnTransitions => &mut weights.column_transitions,
+                Metric::WellDepth => &mut weights.well_depth,
+                Metric::TSlots => &mut weights.t_slots,
+                Metric::Parity => &mut weights.parity,
+            };
+            *weight = value;
+        }
+
+        Ok(weights)
+    }
+}
+
+/// How `move` orders its results, and how many of them it keeps.
+#[derive(Clone, Copy, Debug)]
+pub struct Ranking {
+    pub sort_by: Metric,
+    pub top: Option<usize>,
+    pub weights: Weights,
+}
+
+impl Ranking {
+    /// Orders boards best first and keeps the `top` of them.
+    /// Boards that are equally good stay in their original order.
+    pub fn apply<T>(&self, items: Vec<T>, bits: impl Fn(&T) -> Bits) -> Vec<T> {
+        let mut keyed = items
+            .into_iter()
+            .map(|x| (Metrics::of(&bits(&x)).get(self.sort_by, &self.weights), x))
+            .collect::<Vec<_>>();
+        keyed.sort_by(|a, b| {
+            let order = a.0.total_cmp(&b.0);
+            if self.sort_by.higher_is_better() {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+
+        keyed
+            .into_iter()
+            .map(|x| x.1)
+            .take(self.top.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
@@ -0,0 +1,116 @@
+use itertools::Itertools;
+
+use crate::{board::Board, grid::Grid, piece::Piece, traits::CollectVec};
+
+/// Parses fields in the multi-line format of sfinder's `field.txt`.
+///
+/// Each row is on its own line with the top row first, using `X` for garbage, `_` for empty cells,
+/// or piece letters for colored cells. An optional first line gives the height of the field,
+/// in which case the rows are padded with empty rows up to that height.
+/// Pages are separated by blank lines.
+#[allow(clippy::module_name_repetitions)]
+pub fn field_to_grid(s: &str) -> Result<Grid, String> {
+    let mut pages = vec![];
+    for block in s.lines().map(str::trim).collect_vec().split(|x| x.is_empty()) {
+        if block.is_empty() {
+            continue;
+        }
+
+        let (height, rows) = match block[0].parse::<usize>() {
+            Ok(h) => (Some(h), &block[1..]),
+            Err(_) => (None, block),
+        };
+
+        let mut data = rows
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(y, row)| {
+                row.chars()
+                    .enumerate()
+                    .map(|(x, c)| match c.to_ascii_uppercase() {
+                        'X' => Ok(Piece::G),
+                        '_' => Ok(Piece::E),
+                        c @ ('I' | 'J' | 'O' | 'L' | 'Z' | 'S' | 'T' | 'G' | 'D') => {
+                            Ok(c.to_string().parse().unwrap())
+                        }
+                        _ => Err(format!(
+                            "unexpected `{c}` in row {} column {} of field, expected one of `X_IJOLZSTGD`",
+                            rows.len() - y,
+                            x + 1
+                        )),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let width = rows.first().map_or(0, |x| x.chars().count());
+        if let Some(row) = rows.iter().position(|x| x.chars().count() != width) {
+            return Err(format!(
+                "row {} of field is {} cells wide, but row 1 is {width}",
+                row + 1,
+                rows[row].chars().count()
+            ));
+        }
+
+        if let Some(h) = height {
+            if data.len() > h {
+                return Err(format!(
+                    "field has {} rows, which is more than its height of {h}",
+                    data.len()
+                ));
+            }
+
+            // a field with only a height is an empty field of the usual width
+            let w = data.iter().map(Vec::len).max().unwrap_or(10);
+            data.resize(h, vec![Piece::E; w]);
+        }
+
+        let mut board = Board {
+            data,
+            comment: None,
+            margin: 0,
+        };
+        board.deoptimize();
+        pages.push(board);
+    }
+
+    if pages.is_empty() {
+        return Err("field has no rows".to_string());
+    }
+
+    Ok(Grid(pages))
+}
+
+/// Writes `grid` in the multi-line format read by [`field_to_grid`], starting each page with its height.
+/// Empty rows above the highest filled cell are left out, but an empty page still gets one row
+/// so that it keeps its width and more than one line.
+#[allow(clippy::module_name_repetitions)]
+#[must_use]
+pub fn grid_to_field(grid: &Grid) -> String {
+    grid.pages()
+        .iter()
+        .map(|page| {
+            let mut rows = page
+                .rows()
+                .iter()
+                .rev()
+                .skip_while(|x| x.iter().all(|x| !x.is_filled()))
+                .map(|x| {
+                    x.iter()
+                        .map(|x| match x {
+                            Piece::E => '_',
+                            Piece::G | Piece::D => 'X',
+                            p => p.to_string().chars().next().unwrap(),
+                        })
+                        .collect::<String>()
+                })
+                .vec();
+            if rows.is_empty() {
+                rows.push("_".repeat(page.width().max(1)));
+            }
+
+            format!("{}\n{}", page.height().max(rows.len()), rows.join("\n"))
+        })
+        .join("\n\n")
+}
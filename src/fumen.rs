@@ -1,25 +1,40 @@
-use fumen::{CellColor, Fumen};
+use fumen::{CellColor, Fumen, PieceType, RotationState};
+use regex::Regex;
 
 use crate::{board::Board, grid::Grid, piece::Piece};
 
+/// The width of a fumen field.
+pub const FUMEN_WIDTH: usize = 10;
+/// The height of a fumen field, not counting the garbage row.
+pub const FUMEN_HEIGHT: usize = 23;
+
+/// Encodes every page of `grid` into a fumen.
+/// Empty cells outside of the 10x23 fumen field (such as the margin) are dropped,
+/// but a filled cell there is an error since fumen cannot represent it.
 #[allow(clippy::module_name_repetitions)]
-#[must_use]
-pub fn grid_to_fumen(grid: &Grid) -> Fumen {
+pub fn grid_to_fumen(grid: &Grid) -> anyhow::Result<Fumen> {
     let mut f = Fumen::default();
-    for page in grid.pages() {
+    for (n, page) in grid.pages().iter().enumerate() {
         let p = f.add_page();
-        let mut field = [[CellColor::Empty; 10]; 23];
+        let mut field = [[CellColor::Empty; FUMEN_WIDTH]; FUMEN_HEIGHT];
 
-        for (i, row) in page.rows().iter().enumerate() {
-            for (c, t) in row.iter().enumerate().take(10.min(row.len())) {
-                field[i][c] = t.cell_color();
+        for (y, row) in page.rows().iter().enumerate() {
+            for (x, t) in row.iter().enumerate() {
+                if x < FUMEN_WIDTH && y < FUMEN_HEIGHT {
+                    field[y][x] = t.cell_color();
+                } else if t.is_filled() {
+                    anyhow::bail!(
+                        "page {} has a filled cell at ({x}, {y}), which is outside of the {FUMEN_WIDTH}x{FUMEN_HEIGHT} field fumen supports; use a text output instead",
+                        n + 1
+                    );
+                }
             }
         }
         p.field = field;
         p.comment.clone_from(&page.comment);
     }
 
-    f
+    Ok(f)
 }
 
 #[allow(clippy::module_name_repetitions)]
@@ -58,3 +73,311 @@ pub fn fumen_to_grid(fumen: &Fumen) -> Grid {
             .collect::<Vec<_>>(),
     )
 }
+
+/// Pulls the fumen data out of `s`, which may be bare data (`v115@...`) or a link from any viewer
+/// (`https://fumen.zui.jp/?v115@...`, harddrop, qv, ...), and decodes it.
+/// Both `v115` and the older `v110` encodings are supported.
+pub fn decode_fumen(s: &str) -> Result<Fumen, String> {
+    let re = Regex::new(r"(\d{3})@([^&#\s]*)").unwrap();
+    let caps = re
+        .captures(s)
+        .ok_or(format!("`{s}` does not contain any fumen data"))?;
+    let data = &caps[2];
+
+    match &caps[1] {
+        "115" => Fumen::decode(&format!("v115@{data}"))
+            .map_err(|_| format!("invalid v115 fumen data `{data}`")),
+        "110" => decode_v110(data).ok_or(format!("invalid v110 fumen data `{data}`")),
+        v => Err(format!("unsupported fumen version v{v}")),
+    }
+}
+
+/// `v110` fields are 21 rows tall with a garbage row underneath, as opposed to the 23 rows of `v115`.
+const V110_HEIGHT: usize = 21;
+const V110_BLOCKS: usize = (V110_HEIGHT + 1) * 10;
+
+#[allow(clippy::too_many_lines)]
+fn decode_v110(data: &str) -> Option<Fumen> {
+    let mut iter = data
+        .chars()
+        .filter(|&c| c != '?')
+        .map(from_base64)
+        .peekable();
+    let mut fumen = Fumen::default();
+    let mut empty_fields = 0;
+
+    while iter.peek().is_some() {
+        let page = fumen.add_page();
+        if empty_fields == 0 {
+            let mut delta = [[0; 10]; V110_HEIGHT + 1];
+            let (mut x, mut y) = (0, 0);
+            while y != V110_HEIGHT + 1 {
+                let number = iter.next()?? + 64 * iter.next()??;
+                let value = number / V110_BLOCKS;
+                let repeats = number % V110_BLOCKS + 1;
+                for _ in 0..repeats {
+                    if y == V110_HEIGHT + 1 {
+                        return None;
+                    }
+                    delta[y][x] = value;
+                    x += 1;
+                    if x == 10 {
+                        y += 1;
+                        x = 0;
+                    }
+                }
+            }
+
+            if delta == [[8; 10]; V110_HEIGHT + 1] {
+                empty_fields = iter.next()??;
+            }
+
+            for (y, row) in delta.iter().enumerate() {
+                let target = if y == V110_HEIGHT {
+                    &mut page.garbage_row
+                } else {
+                    &mut page.field[V110_HEIGHT - 1 - y]
+                };
+
+                for (cell, d) in target.iter_mut().zip(row) {
+                    *cell = cell_color((*d + *cell as usize).checked_sub(8)?)?;
+                }
+            }
+        } else {
+            empty_fields -= 1;
+        }
+
+        let number = iter.next()?? + iter.next()?? * 64 + iter.next()?? * 64 * 64;
+        let piece_type = number % 8;
+        let piece_rot = number / 8 % 4;
+        let piece_pos = number / 32 % V110_BLOCKS;
+
+        page.piece = if piece_type == 0 {
+            None
+        } else {
+            let kind = match piece_type {
+                1 => PieceType::I,
+                2 => PieceType::L,
+                3 => PieceType::O,
+                4 => PieceType::Z,
+                5 => PieceType::T,
+                6 => PieceType::J,
+                _ => PieceType::S,
+            };
+            let rotation = match piece_rot {
+                0 => RotationState::South,
+                1 => RotationState::East,
+                2 => RotationState::North,
+                _ => RotationState::West,
+            };
+            let x = u32::try_from(piece_pos % 10).ok()?;
+            let y = u32::try_from(V110_HEIGHT - 1).ok()?
+                .checked_sub(u32::try_from(piece_pos / 10).ok()?)?;
+
+            // fumen centers are shifted from SRS true rotation centers for these pieces
+            Some(fumen::Piece {
+                kind,
+                rotation,
+                x: match (kind, rotation) {
+                    (PieceType::S, RotationState::East) => x.checked_sub(1)?,
+                    (PieceType::Z | PieceType::O, RotationState::West)
+                    | (PieceType::O | PieceType::I, RotationState::South) => x + 1,
+                    _ => x,
+                },
+                y: match (kind, rotation) {
+                    (PieceType::S | PieceType::Z | PieceType::O, RotationState::North)
+                    | (PieceType::O | PieceType::I, RotationState::West) => y.checked_sub(1)?,
+                    _ => y,
+                },
+            })
+        };
+
+        let flags = number / 32 / V110_BLOCKS;
+        page.rise = flags & 0b1 != 0;
+        page.mirror = flags & 0b10 != 0;
+        let guideline = flags & 0b100 != 0;
+        let comment = flags & 0b1000 != 0;
+        page.lock = flags & 0b10000 == 0;
+
+        if comment {
+            let mut length = iter.next()?? + iter.next()?? * 64;
+            let mut escaped = String::new();
+            while length > 0 {
+                let mut number = 0;
+                for i in 0..5 {
+                    number += iter.next()?? * 64usize.pow(i);
+                }
+
+                for _ in 0..length.min(4) {
+                    escaped.push(char::from_u32(u32::try_from(number % 96 + 0x20).ok()?)?);
+                    length -= 1;
+                    number /= 96;
+                }
+            }
+
+            page.comment = Some(unescape(&escaped));
+        }
+
+        if fumen.pages.len() == 1 {
+            fumen.guideline = guideline;
+        }
+    }
+
+    Some(fumen)
+}
+
+fn from_base64(c: char) -> Option<usize> {
+    Some(match c {
+        'A'..='Z' => c as usize - 'A' as usize,
+        'a'..='z' => c as usize - 'a' as usize + 26,
+        '0'..='9' => c as usize - '0' as usize + 52,
+        '+' => 62,
+        '/' => 63,
+        _ => return None,
+    })
+}
+
+fn cell_color(value: usize) -> Option<CellColor> {
+    Some(match value {
+        0 => CellColor::Empty,
+        1 => CellColor::I,
+        2 => CellColor::L,
+        3 => CellColor::O,
+        4 => CellColor::Z,
+        5 => CellColor::T,
+        6 => CellColor::J,
+        7 => CellColor::S,
+        8 => CellColor::Grey,
+        _ => return None,
+    })
+}
+
+/// Reverses javascript's `escape`, which is how fumen stores comments.
+fn unescape(s: &str) -> String {
+    let mut units = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            let len = if chars.peek() == Some(&'u') {
+                chars.next();
+                4
+            } else {
+                2
+            };
+
+            let hex = chars.by_ref().take(len).collect::<String>();
+            units.push(u16::from_str_radix(&hex, 16).unwrap_or(0));
+        } else {
+            units.push(u16::try_from(u32::from(c)).unwrap_or(0));
+        }
+    }
+
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use fumen::CellColor;
+    use itertools::Itertools;
+
+    use super::{decode_fumen, fumen_to_grid, grid_to_fumen, unescape, V110_HEIGHT};
+    use crate::{field::field_to_grid, piece::Piece};
+
+    type Field = [[CellColor; 10]; 23];
+
+    /// Encodes `pages` as `v110` data without pieces or comments, where each page lists how its cells changed.
+    fn encode_v110(pages: &[Field]) -> String {
+        let digit = |n: usize| {
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"[n] as char
+        };
+        let mut data = String::new();
+        let mut previous = [[CellColor::Empty; 10]; 23];
+        for field in pages {
+            // rows from the top, and then the garbage row, which is left empty
+            let deltas = (0..V110_HEIGHT)
+                .rev()
+                .flat_map(|y| (0..10).map(move |x| (y, x)))
+                .map(|(y, x)| field[y][x] as usize + 8 - previous[y][x] as usize)
+                .chain([8; 10])
+                .collect_vec();
+            for run in deltas.chunk_by(|a, b| a == b) {
+                let number = run[0] * 220 + run.len() - 1;
+                data.extend([digit(number % 64), digit(number / 64)]);
+            }
+            if deltas.iter().all(|&x| x == 8) {
+                data.push(digit(0));
+            }
+
+            data.push_str("AAA");
+            previous = *field;
+        }
+
+        data
+    }
+
+    fn field(rows: &[&str]) -> Field {
+        let grid = field_to_grid(&rows.join("\n")).unwrap();
+        grid_to_fumen(&grid).unwrap().pages[0].field
+    }
+
+    #[test]
+    fn decodes_links_from_any_viewer() {
+        let data = decode_fumen("v115@bhH8LeAgH").unwrap();
+        for link in [
+            "https://fumen.zui.jp/?v115@bhH8LeAgH",
+            "https://harddrop.com/fumen/?v115@bhH8LeAgH#english",
+            "https://knewjade.github.io/fumen-for-mobile/#?d=v115@bhH8LeAgH&m=1",
+        ] {
+            assert_eq!(decode_fumen(link), Ok(data.clone()), "{link}");
+        }
+
+        assert!(decode_fumen("no fumen here").is_err());
+        assert!(decode_fumen("v120@AAA").is_err());
+    }
+
+    #[test]
+    fn round_trips_v115() {
+        let grid = field_to_grid("TTT_______\nXIIIIXXXX_\n\nOO________\nOO_____SSZ").unwrap();
+        let data = grid_to_fumen(&grid).unwrap().encode();
+        let decoded = fumen_to_grid(&decode_fumen(&data).unwrap());
+        assert_eq!(grid_to_fumen(&decoded).unwrap().encode(), data);
+        assert_eq!(decoded.pages().len(), 2);
+        assert_eq!(decoded.pages()[0].rows()[0][1], Piece::I);
+    }
+
+    #[test]
+    fn decodes_v110() {
+        let pages = [
+            field(&["__________", "XXXXXXXXX_"]),
+            field(&["L_________", "LLL_____OO", "XXXXXXXXX_"]),
+            field(&["L_________", "LLL_____OO", "XXXXXXXXX_"]),
+        ];
+        let data = encode_v110(&pages);
+        let decoded = decode_fumen(&format!("v110@{data}")).unwrap();
+        assert_eq!(decoded.pages.iter().map(|x| x.field).collect_vec(), pages);
+
+        // fumen splits long data with `?`, which carries no meaning
+        let (a, b) = data.split_at(data.len() / 2);
+        assert_eq!(decode_fumen(&format!("v110@{a}?{b}")), Ok(decoded.clone()));
+
+        // decoding v110 and encoding as v115 keeps every page
+        let v115 = grid_to_fumen(&fumen_to_grid(&decoded)).unwrap().encode();
+        let again = decode_fumen(&v115).unwrap();
+        assert_eq!(again.pages.iter().map(|x| x.field).collect_vec(), pages);
+
+        assert!(decode_fumen("v110@AAA").is_err());
+    }
+
+    #[test]
+    fn rejects_cells_outside_the_field() {
+        let grid = field_to_grid("X__________").unwrap();
+        assert!(grid_to_fumen(&grid).is_ok());
+        let grid = field_to_grid("__________X").unwrap();
+        assert!(grid_to_fumen(&grid).is_err());
+    }
+
+    #[test]
+    fn unescapes_comments() {
+        assert_eq!(unescape("a%20b%u3042"), "a b\u{3042}");
+    }
+}
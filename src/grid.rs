@@ -18,10 +18,23 @@ impl Grid {
         )
     }
 
-    pub fn new(str: impl Display) -> Self {
+    pub fn new(str: impl Display) -> Result<Self, String> {
         let s = str.to_string();
-        let z = s.split(';').map(Board::new).collect();
-        Self(z)
+        let pages = s.split(';').collect::<Vec<_>>();
+        let z = pages
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                Board::new(x).map_err(|e| {
+                    if pages.len() > 1 {
+                        format!("on page {}: {e}", i + 1)
+                    } else {
+                        e
+                    }
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self(z))
     }
 
     pub fn from_pages<I>(pages: I) -> Self
@@ -72,8 +85,7 @@ impl Grid {
         &mut self.0
     }
 
-    #[must_use]
-    pub fn fumen(&self) -> Fumen {
+    pub fn fumen(&self) -> anyhow::Result<Fumen> {
         grid_to_fumen(self)
     }
 
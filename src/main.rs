@@ -3,22 +3,30 @@
 
 use program::Sfce;
 
+pub mod bag;
 pub mod bits;
 pub mod board;
 pub mod board_parser;
 pub mod commands;
 pub mod data;
+pub mod db;
+pub mod eval;
+pub mod field;
 pub mod fumen;
 pub mod grid;
 pub mod input;
 pub mod pattern;
+pub mod pc;
 pub mod piece;
 pub mod placement;
 pub mod program;
+pub mod randomizer;
 pub mod ranged;
+pub mod rng;
+pub mod shard;
 pub mod text;
+pub mod tile;
 pub mod traits;
-pub mod set;
 
 fn main() {
     let mut p = Sfce::new();
@@ -1,14 +1,17 @@
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{BTreeMap, BTreeSet, HashSet},
     fmt::Display,
     str::FromStr,
 };
 
 use chumsky::Parser;
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     piece::Piece,
+    rng::Rng,
+    text::parse_error,
     traits::{CollectVec, FullyDedup},
 };
 
@@ -18,13 +21,50 @@ pub struct Pattern {
 }
 
 impl Pattern {
+    /// Parses a pattern, or a pattern file with one pattern per line where each line adds to the set.
+    /// Anything after a `#` is a comment.
     pub fn new(s: impl Display) -> Result<Self, String> {
         let t = s.to_string();
-        let x = parse::parser()
-            .parse(&t)
-            .into_output()
-            .ok_or("malformed pattern".to_string());
-        x
+        let lines = t
+            .lines()
+            .enumerate()
+            .map(|(i, x)| (i, x.split('#').next().unwrap_or_default().trim_end()))
+            .filter(|(_, x)| !x.trim().is_empty())
+            .vec();
+
+        if lines.len() <= 1 {
+            let line = lines.first().map_or("", |x| x.1);
+            let x = parse::parser()
+                .parse(line)
+                .into_result()
+                .map_err(|e| parse_error(line, &e))?;
+            x.count()?;
+            return Ok(x);
+        }
+
+        let mut alternatives = vec![];
+        for (i, line) in lines {
+            let p = parse::parser()
+                .parse(line)
+                .into_result()
+                .map_err(|e| format!("on line {}: {}", i + 1, parse_error(line, &e)))?;
+            alternatives.extend(p.alternatives());
+        }
+
+        let x = Self {
+            parts: vec![Part::Or(alternatives)],
+        };
+        x.count()?;
+        Ok(x)
+    }
+
+    /// The patterns that this pattern is a union of, which is only itself unless it is a single [`Part::Or`].
+    #[must_use]
+    pub fn alternatives(self) -> Vec<Self> {
+        match self.parts.as_slice() {
+            [Part::Or(x)] => x.clone(),
+            _ => vec![self],
+        }
     }
 
     #[must_use]
@@ -32,197 +72,185 @@ impl Pattern {
         self.clone().into_iter().vec()
     }
 
+    /// The queues of this pattern, keeping only one queue out of those that can be played in the same orders with hold.
     pub fn into_iter_with_hold(self) -> impl Iterator<Item = Queue> {
-        self.into_iter().fully_dedup_by(Queue::translatable)
+        self.into_iter().fully_dedup_by_key(Queue::hold_equivalent)
     }
 
     #[must_use]
     pub fn queues_with_hold(&self) -> Vec<Queue> {
         self.clone().into_iter_with_hold().vec()
     }
+
+    /// The queues of this pattern that `other` has (or doesn't have, if `keep` is false),
+    /// where `hold` makes queues that can be played as each other with hold count as the same queue.
+    fn filter_by(&self, other: &Self, hold: bool, keep: bool) -> Vec<Queue> {
+        if hold {
+            let set: HashSet<Queue> = other.clone().into_iter().map(|x| x.hold_equivalent()).collect();
+            self.clone()
+                .into_iter_with_hold()
+                .filter(|q| set.contains(&q.hold_equivalent()) == keep)
+                .vec()
+        } else {
+            let set: HashSet<Queue> = other.clone().into_iter().collect();
+            self.clone()
+                .into_iter()
+                .fully_dedup()
+                .filter(|q| set.contains(q) == keep)
+                .vec()
+        }
+    }
+
+    /// The queues of either pattern.
+    #[must_use]
+    pub fn union(&self, other: &Self, hold: bool) -> Vec<Queue> {
+        let queues = self.clone().into_iter().chain(other.clone());
+        if hold {
+            queues.fully_dedup_by_key(Queue::hold_equivalent).vec()
+        } else {
+            queues.fully_dedup().vec()
+        }
+    }
+
+    /// The queues of this pattern that are also in `other`.
+    #[must_use]
+    pub fn intersection(&self, other: &Self, hold: bool) -> Vec<Queue> {
+        self.filter_by(other, hold, true)
+    }
+
+    /// The queues of this pattern that aren't in `other`.
+    #[must_use]
+    pub fn difference(&self, other: &Self, hold: bool) -> Vec<Queue> {
+        self.filter_by(other, hold, false)
+    }
+}
+
+/// Pieces in the order they are dealt, where the first one is the current piece.
+/// A queue can start with a piece already in hold, which is written like `(T)IJSZ`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct Queue {
+    pieces: Vec<Piece>,
+    hold: Option<Piece>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Default)]
-pub struct Queue(Vec<Piece>);
+/// A piece that can be placed next while working through a queue with hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HoldStep {
+    pub piece: Piece,
+    /// How many pieces of the queue have been dealt afterwards.
+    pub taken: usize,
+    /// The piece in hold afterwards.
+    pub hold: Option<Piece>,
+    /// Whether or not hold had to be pressed.
+    pub uses_hold: bool,
+}
 
 impl Queue {
     #[must_use]
     pub fn empty() -> Self {
-        Self(vec![])
+        Self::default()
     }
 
     #[must_use]
     pub fn pieces(&self) -> &[Piece] {
-        &self.0
+        &self.pieces
     }
 
+    /// The piece in hold before the first piece is placed.
     #[must_use]
-    pub fn len(&self) -> usize {
-        self.0.len()
+    pub fn hold(&self) -> Option<Piece> {
+        self.hold
     }
 
     #[must_use]
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
+    pub fn len(&self) -> usize {
+        self.pieces.len()
     }
 
-    pub fn iter(&self) -> std::slice::Iter<'_, Piece> {
-        self.0.iter()
+    /// The amount of pieces that get placed, which includes the held one.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.len() + usize::from(self.hold.is_some())
     }
 
+    /// The pieces that can be placed next once `taken` pieces of the queue have been dealt and `hold` is held.
+    /// Every piece is placed in the end, including the held one.
     #[must_use]
-    pub fn translatable(&self, b: &Queue) -> bool {
-        #[derive(Debug, PartialEq, Eq, Clone, Hash)]
-        struct State {
-            queue: VecDeque<Piece>,
-            hold: Option<Piece>,
-            target_index: usize,
-        }
-        if self.len() != b.len() {
-            return false; // Lengths must match
+    pub fn next_steps(&self, taken: usize, hold: Option<Piece>, can_hold: bool) -> Vec<HoldStep> {
+        let step = |piece, taken, hold, uses_hold| HoldStep {
+            piece,
+            taken,
+            hold,
+            uses_hold,
+        };
+        let mut steps = vec![];
+        let current = self.pieces.get(taken).copied();
+        if let Some(current) = current {
+            steps.push(step(current, taken + 1, hold, false));
         }
 
-        let initial_queue: VecDeque<Piece> = self.iter().copied().collect();
-        let mut visited = HashSet::new();
-        let mut stack = Vec::new();
-
-        // Initial state: all pieces in the queue, no hold, and starting at target index 0
-        stack.push(State {
-            queue: initial_queue,
-            hold: None,
-            target_index: 0,
-        });
-
-        while let Some(state) = stack.pop() {
-            if state.target_index == b.len() {
-                return true; // Successfully matched the entire target sequence
-            }
-
-            if visited.contains(&state) {
-                continue; // Avoid revisiting the same state
+        if can_hold {
+            match (hold, current) {
+                (Some(swap), Some(current)) => steps.push(step(swap, taken + 1, Some(current), true)),
+                (Some(last), None) => steps.push(step(last, taken, None, true)),
+                (None, Some(current)) => {
+                    if let Some(&next) = self.pieces.get(taken + 1) {
+                        steps.push(step(next, taken + 2, Some(current), true));
+                    }
+                }
+                (None, None) => {}
             }
+        }
 
-            visited.insert(state.clone());
-
-            let target = b.0[state.target_index];
+        steps
+    }
 
-            // Option 1: Place the front of the queue
-            if let Some(&front) = state.queue.front() {
-                if front == target {
-                    let mut new_queue = state.queue.clone();
-                    new_queue.pop_front();
-                    stack.push(State {
-                        queue: new_queue,
-                        hold: state.hold,
-                        target_index: state.target_index + 1,
-                    });
-                }
-            }
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-            // Option 2: Place the hold piece
-            if let Some(held_piece) = state.hold {
-                if held_piece == target {
-                    let mut new_queue = state.queue.clone();
-                    let new_hold = new_queue.pop_front();
-                    stack.push(State {
-                        queue: new_queue,
-                        hold: new_hold,
-                        target_index: state.target_index + 1,
-                    });
-                }
-            }
+    pub fn iter(&self) -> std::slice::Iter<'_, Piece> {
+        self.pieces.iter()
+    }
 
-            // Option 3: Hold the front of the queue (if hold is empty or hold does not match target)
-            if let Some(front) = state.queue.front() {
-                if state.hold.is_none() || state.hold != Some(target) {
-                    let mut new_queue = state.queue.clone();
-                    new_queue.pop_front();
-                    stack.push(State {
-                        queue: new_queue,
-                        hold: Some(*front),
-                        target_index: state.target_index,
-                    });
-                }
-            }
+    /// The same queue for every queue that can be placed in exactly the same orders with unlimited hold.
+    ///
+    /// Whichever of the held and current piece gets placed, the other one ends up paired with the next piece,
+    /// so the order of the first two pieces never matters and is all that hold can change.
+    #[must_use]
+    pub fn hold_equivalent(&self) -> Self {
+        let mut pieces = self.hold.into_iter().chain(self.pieces.iter().copied()).vec();
+        if pieces.len() >= 2 && pieces[0] > pieces[1] {
+            pieces.swap(0, 1);
         }
 
-        false // No valid sequence found
+        Self { pieces, hold: None }
     }
 
+    /// Every order the pieces of this queue can be placed in using hold,
+    /// pressing hold at most `max_holds` times if given.
     #[must_use]
-    pub fn hold_queues(&self) -> HashSet<Self> {
-        #[derive(Debug, PartialEq, Eq, Clone, Hash)]
-        struct State {
-            queue: VecDeque<Piece>,
-            hold: Option<Piece>,
-            sequence: Vec<Piece>,
-        }
+    pub fn hold_queues(&self, max_holds: Option<usize>) -> HashSet<Self> {
         let mut result = HashSet::new();
         let mut visited = HashSet::new();
-        let mut stack = Vec::new();
-
-        // Initial state: no hold, starting queue, and an empty sequence
-        stack.push(State {
-            queue: VecDeque::from(self.0.clone()),
-            hold: None,
-            sequence: vec![],
-        });
-
-        while let Some(state) = stack.pop() {
-            // If we've already visited this state, skip it
-            if !visited.insert((state.queue.clone(), state.hold, state.sequence.clone())) {
+        // pieces dealt, held piece, holds used and the pieces placed so far
+        let mut stack = vec![(0, self.hold, 0, vec![])];
+        while let Some((taken, hold, holds, sequence)) = stack.pop() {
+            if !visited.insert((taken, hold, holds, sequence.clone())) {
                 continue;
             }
 
-            // If the queue is empty, finalize the sequence
-            if state.queue.is_empty() {
-                if let Some(held_piece) = state.hold {
-                    // If there's a piece in the hold, it must be placed
-                    let mut final_sequence = state.sequence.clone();
-                    final_sequence.push(held_piece);
-                    result.insert(Self(final_sequence));
-                } else {
-                    // If no hold piece remains, just add the sequence
-                    result.insert(Self(state.sequence.clone()));
-                }
+            if taken == self.len() && hold.is_none() {
+                result.insert(sequence.into_iter().collect());
                 continue;
             }
 
-            // Option 1: Place the front piece from the queue
-            if let Some(&front) = state.queue.front() {
-                let mut new_queue = state.queue.clone();
-                let mut new_sequence = state.sequence.clone();
-                new_sequence.push(front); // Add the placed piece to the sequence
-                new_queue.pop_front();
-                stack.push(State {
-                    queue: new_queue,
-                    hold: state.hold,
-                    sequence: new_sequence,
-                });
-            }
-
-            // Option 2: Swap the front piece with the hold piece
-            if let Some(&front) = state.queue.front() {
-                let mut new_queue = state.queue.clone();
-                new_queue.pop_front(); // Remove the front piece from the queue
-
-                if let Some(held_piece) = state.hold {
-                    // If there's a piece in the hold, swap it with the front
-                    let mut swapped_queue = new_queue.clone();
-                    swapped_queue.push_front(held_piece); // Add the held piece back to the front
-                    stack.push(State {
-                        queue: swapped_queue,
-                        hold: Some(front),
-                        sequence: state.sequence.clone(),
-                    });
-                } else {
-                    // If the hold is empty, store the front piece in the hold
-                    stack.push(State {
-                        queue: new_queue,
-                        hold: Some(front),
-                        sequence: state.sequence.clone(),
-                    });
-                }
+            let can_hold = max_holds.is_none_or(|x| holds < x);
+            for step in self.next_steps(taken, hold, can_hold) {
+                let mut sequence = sequence.clone();
+                sequence.push(step.piece);
+                stack.push((step.taken, step.hold, holds + usize::from(step.uses_hold), sequence));
             }
         }
 
@@ -230,20 +258,51 @@ impl Queue {
     }
 }
 
+impl FromStr for Queue {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let piece = |c: char| match Piece::from_str(&c.to_string()) {
+            Ok(p) if BAG.contains(&p) => Ok(p),
+            _ => Err(format!("`{c}` in `{s}` is not a piece")),
+        };
+        let (hold, rest) = match s.strip_prefix('(').and_then(|x| x.split_once(')')) {
+            Some((h, rest)) if h.chars().count() == 1 => (Some(piece(h.chars().next().unwrap())?), rest),
+            _ => (None, s),
+        };
+
+        Ok(Self {
+            pieces: rest.chars().map(piece).collect::<Result<_, _>>()?,
+            hold,
+        })
+    }
+}
+
 impl Display for Queue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(h) = self.hold {
+            write!(f, "({h})")?;
+        }
         write!(
             f,
             "{}",
-            self.0.iter().map(std::string::ToString::to_string).join("")
+            self.pieces.iter().map(std::string::ToString::to_string).join("")
         )
     }
 }
 
+impl FromIterator<Piece> for Queue {
+    fn from_iter<T: IntoIterator<Item = Piece>>(iter: T) -> Self {
+        Self {
+            pieces: iter.into_iter().collect(),
+            hold: None,
+        }
+    }
+}
+
 impl Extend<Piece> for Queue {
     fn extend<T: IntoIterator<Item = Piece>>(&mut self, iter: T) {
         for i in iter {
-            self.0.push(i);
+            self.pieces.push(i);
         }
     }
 }
@@ -272,6 +331,26 @@ impl FromStr for Pattern {
     }
 }
 
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let [Part::Or(alternatives)] = self.parts.as_slice() {
+            return write!(f, "{}", alternatives.iter().map(Part::alternative).join("|"));
+        }
+
+        for (i, part) in self.parts.iter().enumerate() {
+            // commas are only needed to keep counts and bags apart, so runs of pieces are written together
+            let needs_comma =
+                i != 0 && !matches!((&self.parts[i - 1], part), (Part::Single(_) | Part::Hold(_), Part::Single(_)));
+            if needs_comma {
+                write!(f, ",")?;
+            }
+            write!(f, "{part}")?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Part {
     All(Box<Self>),
@@ -281,177 +360,441 @@ pub enum Part {
     Except(Vec<Self>),
     Or(Vec<Pattern>),
     Wildcard,
+    /// The piece that starts in hold, which can only begin a pattern.
+    Hold(Piece),
 }
 
-pub struct Iter {
-    queue: VecDeque<Queue>,
-}
+/// The 7 pieces that wildcards and bags choose from.
+pub const BAG: [Piece; 7] = [
+    Piece::I,
+    Piece::J,
+    Piece::O,
+    Piece::L,
+    Piece::Z,
+    Piece::S,
+    Piece::T,
+];
 
-impl Iter {
+impl Part {
+    /// The choices this part picks from. Repeating a part picks that many distinct choices in order.
+    /// The nodes this part chooses from, where `None` is a choice with too many queues to count.
+    fn choices(&self) -> Vec<Option<Node>> {
+        match self {
+            Self::Single(p) => vec![Some(Node::Piece(*p))],
+            Self::Hold(p) => vec![Some(Node::Hold(*p))],
+            Self::Wildcard => BAG.map(|x| Some(Node::Piece(x))).to_vec(),
+            Self::Bag(parts) => parts.iter().map(Part::node).vec(),
+            Self::Except(exclusions) => BAG
+                .into_iter()
+                .filter(|x| !exclusions.contains(&Self::Single(*x)))
+                .map(|x| Some(Node::Piece(x)))
+                .vec(),
+            Self::Or(patterns) => patterns.iter().map(Pattern::node).vec(),
+            Self::Count(..) | Self::All(_) => vec![self.node()],
+        }
+    }
+
+    /// Writes a pattern as one alternative of a group, adding parentheses if it would otherwise merge into the group.
+    fn alternative(pattern: &Pattern) -> String {
+        match pattern.parts.as_slice() {
+            [Part::Or(_)] => format!("({pattern})"),
+            _ => pattern.to_string(),
+        }
+    }
+
+    /// The shortest part that picks one of `pieces`, which must not be empty.
+    fn one_of(pieces: &BTreeSet<Piece>) -> Self {
+        let excluded = BAG.into_iter().filter(|x| !pieces.contains(x)).vec();
+        match pieces.len() {
+            1 => Self::Single(*pieces.first().unwrap()),
+            7 => Self::Wildcard,
+            n if excluded.len() + 1 < n => Self::Except(excluded.into_iter().map(Self::Single).vec()),
+            _ => Self::Bag(pieces.iter().copied().map(Self::Single).vec()),
+        }
+    }
+
+    /// The shortest part that picks `count` distinct pieces out of `pieces` in every order.
     #[must_use]
-    pub fn new(pattern: &Pattern) -> Self {
-        let mut queue = VecDeque::new();
-        queue.push_back(Queue::empty()); // Start with an empty sequence
-        let mut iter = Self { queue };
-        iter.expand_pattern(pattern);
-        iter
-    }
-
-    #[allow(clippy::too_many_lines)]
-    fn expand_pattern(&mut self, pattern: &Pattern) {
-        for part in &pattern.parts {
-            let mut new_queue = VecDeque::new();
-
-            while let Some(current) = self.queue.pop_front() {
-                match part {
-                    Part::All(inner) => {
-                        // Expand the inner pattern to collect all pieces
-                        let inner_iter = Iter::new(&Pattern {
-                            parts: vec![*inner.clone()],
-                        });
-
-                        let results: Vec<_> = inner_iter.collect();
-                        if !results.is_empty() {
-                            // Generate all permutations of the entire collection of pieces
-                            let all_pieces = results.into_iter().concat(); // Flatten all results
-                            for perm in permutations(all_pieces.pieces(), all_pieces.len()) {
-                                let mut combined = current.clone();
-                                combined.0.extend(perm);
-                                new_queue.push_back(combined);
-                            }
-                        }
-                    }
-                    Part::Or(l) => {
-                        for pat in l {
-                            for queue in pat.queues() {
-                                new_queue.push_back(queue);
-                            }
-                        }
-                    }
-                    Part::Count(inner, count) => {
-                        let inner_iter = Iter::new(&Pattern {
-                            parts: vec![*inner.clone()],
-                        });
-
-                        let results: Vec<_> = inner_iter.collect();
-                        assert!(
-                            *count <= results.len(),
-                            "cannot take {count} pieces from a bag that only has {}",
-                            results.len()
-                        );
-                        for combination in permutations(&results, *count) {
-                            let mut combined = current.clone();
-                            combined.0.extend(combination.into_iter().flatten());
-                            new_queue.push_back(combined);
-                        }
-                    }
-                    Part::Single(piece) => {
-                        let mut combined = current.clone();
-                        combined.0.push(*piece);
-                        new_queue.push_back(combined);
-                    }
-                    Part::Bag(parts) => {
-                        for sub_part in parts {
-                            let sub_iter = Iter::new(&Pattern {
-                                parts: vec![sub_part.clone()],
-                            });
-
-                            for sub_result in sub_iter {
-                                let mut combined = current.clone();
-                                combined.0.extend(sub_result);
-                                new_queue.push_back(combined);
-                            }
-                        }
+    pub fn ordered_selections(pieces: &BTreeSet<Piece>, count: usize) -> Self {
+        let bag = Self::one_of(pieces);
+        match bag {
+            _ if count == 1 => bag,
+            Self::Wildcard => Self::Count(Box::new(bag), count),
+            _ if count == pieces.len() => Self::All(Box::new(bag)),
+            _ => Self::Count(Box::new(bag), count),
+        }
+    }
+
+    /// The exact amount of queues this part expands to, without expanding it.
+    pub fn count(&self) -> Result<u128, String> {
+        Ok(self.node().ok_or(TOO_MANY)?.count())
+    }
+
+    /// `None` if this part has too many queues to count.
+    fn node(&self) -> Option<Node> {
+        match self {
+            Self::Single(p) => Some(Node::Piece(*p)),
+            Self::Count(inner, count) => Node::pick(inner.choices(), *count),
+            Self::All(inner) => {
+                let choices = inner.choices();
+                let len = choices.len();
+                Node::pick(choices, len)
+            }
+            _ => Node::pick(self.choices(), 1),
+        }
+    }
+}
+
+impl Display for Part {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Single(p) => write!(f, "{p}"),
+            Self::Hold(p) => write!(f, "({p})"),
+            Self::Wildcard => write!(f, "*"),
+            Self::Bag(parts) => write!(f, "[{}]", parts.iter().join("")),
+            Self::Except(parts) => write!(f, "[^{}]", parts.iter().join("")),
+            Self::Count(inner, count) => write!(f, "{inner}p{count}"),
+            Self::All(inner) => write!(f, "{inner}!"),
+            Self::Or(patterns) => write!(f, "({})", patterns.iter().map(Part::alternative).join("|")),
+        }
+    }
+}
+
+impl Pattern {
+    /// A short pattern that expands to exactly the given queues, ignoring their order and repeats,
+    /// or `None` if there are no queues since no pattern expands to nothing.
+    pub fn from_queues(queues: impl IntoIterator<Item = Queue>) -> Option<Self> {
+        let mut by_len: BTreeMap<(Option<Piece>, usize), BTreeSet<Vec<Piece>>> = BTreeMap::new();
+        for q in queues {
+            by_len.entry((q.hold, q.len())).or_default().insert(q.pieces);
+        }
+
+        let mut alternatives = by_len
+            .into_iter()
+            .map(|((hold, _), x)| Self {
+                parts: hold.map(Part::Hold).into_iter().chain(compress(&x)).collect(),
+            })
+            .vec();
+
+        match alternatives.len() {
+            0 => None,
+            1 => alternatives.pop(),
+            _ => Some(Self {
+                parts: vec![Part::Or(alternatives)],
+            }),
+        }
+    }
+
+    /// `None` if this pattern has too many queues to count.
+    fn node(&self) -> Option<Node> {
+        Node::seq(self.parts.iter().map(Part::node).collect::<Option<Vec<_>>>()?)
+    }
+
+    /// The exact amount of queues this pattern expands to, without expanding it.
+    pub fn count(&self) -> Result<u128, String> {
+        Ok(self.node().ok_or(TOO_MANY)?.count())
+    }
+
+    /// `amount` uniformly random queues out of the ones this pattern expands to, which may repeat.
+    pub fn samples(&self, amount: usize, rng: &mut Rng) -> Result<Vec<Queue>, String> {
+        let node = self.node().ok_or(TOO_MANY)?;
+        let n = node.count();
+        if n == 0 {
+            return Ok(vec![]);
+        }
+
+        Ok((0..amount).filter_map(|_| node.nth(rng.below(n))).vec())
+    }
+}
+
+/// The parts of a sequence that expands to exactly `queues`, which all have the same length.
+fn compress(queues: &BTreeSet<Vec<Piece>>) -> Vec<Part> {
+    let len = queues.first().map_or(0, Vec::len);
+    if len == 0 {
+        return vec![];
+    }
+
+    // the longest prefix that is every ordering of some distinct pieces, followed by the same queues each time
+    for k in (2..=len).rev() {
+        if let Some((pieces, rest)) = permutation_prefix(queues, k) {
+            let mut parts = vec![Part::ordered_selections(&pieces, k)];
+            parts.extend(compress(&rest));
+            return parts;
+        }
+    }
+
+    // queues that are every combination of some prefixes and some suffixes are written as both in sequence
+    for k in 1..len {
+        let prefixes: BTreeSet<Vec<Piece>> = queues.iter().map(|x| x[..k].to_vec()).collect();
+        let suffixes: BTreeSet<Vec<Piece>> = queues.iter().map(|x| x[k..].to_vec()).collect();
+        if prefixes.len() * suffixes.len() == queues.len() && (prefixes.len() > 1 || k == 1) {
+            let mut parts = compress(&prefixes);
+            parts.extend(compress(&suffixes));
+            return parts;
+        }
+    }
+
+    // otherwise split on the first piece, merging the pieces that are followed by the same queues
+    let mut rests: BTreeMap<Piece, BTreeSet<Vec<Piece>>> = BTreeMap::new();
+    for q in queues {
+        rests.entry(q[0]).or_default().insert(q[1..].to_vec());
+    }
+
+    let mut groups: BTreeMap<BTreeSet<Vec<Piece>>, BTreeSet<Piece>> = BTreeMap::new();
+    for (piece, rest) in rests {
+        groups.entry(rest).or_default().insert(piece);
+    }
+
+    let mut alternatives = groups
+        .into_iter()
+        .map(|(rest, pieces)| {
+            let mut parts = vec![Part::one_of(&pieces)];
+            parts.extend(compress(&rest));
+            parts
+        })
+        .vec();
+
+    if alternatives.len() == 1 {
+        return alternatives.pop().unwrap();
+    }
+
+    vec![Part::Or(
+        alternatives.into_iter().map(|x| Pattern { parts: x }).vec(),
+    )]
+}
+
+/// If the first `k` pieces of `queues` are every ordered selection of `k` distinct pieces out of some pieces,
+/// each followed by the same queues, returns those pieces and the queues that follow.
+fn permutation_prefix(
+    queues: &BTreeSet<Vec<Piece>>,
+    k: usize,
+) -> Option<(BTreeSet<Piece>, BTreeSet<Vec<Piece>>)> {
+    let mut rests: BTreeMap<&[Piece], BTreeSet<Vec<Piece>>> = BTreeMap::new();
+    for q in queues {
+        let prefix = &q[..k];
+        if !prefix.iter().all_unique() {
+            return None;
+        }
+        rests.entry(prefix).or_default().insert(q[k..].to_vec());
+    }
+
+    let pieces: BTreeSet<Piece> = rests.keys().flat_map(|x| x.iter().copied()).collect();
+    let selections = (pieces.len() - k + 1..=pieces.len()).product::<usize>();
+    if rests.len() != selections {
+        return None;
+    }
+
+    let mut rests = rests.into_values();
+    let first = rests.next()?;
+    if rests.any(|x| x != first) {
+        return None;
+    }
+
+    Some((pieces, first))
+}
+
+const TOO_MANY: &str = "pattern has more than u128::MAX queues";
+
+/// A pattern compiled down to what is needed for counting its queues and finding the `n`th one,
+/// so that queues can be generated one at a time instead of all at once.
+/// Every node is stored alongside the amount of queues it expands to,
+/// and nodes are only built when all of those amounts fit in a `u128`.
+#[derive(Clone, Debug)]
+enum Node {
+    Piece(Piece),
+    /// The piece that starts in hold.
+    Hold(Piece),
+    /// Every combination of the nodes, in order.
+    Seq(Vec<(Node, u128)>),
+    /// Every ordered selection of some amount of distinct choices.
+    Pick(Vec<(Node, u128)>, usize),
+}
+
+impl Node {
+    fn counted(nodes: Vec<Node>) -> Vec<(Node, u128)> {
+        nodes
+            .into_iter()
+            .map(|x| {
+                let c = x.count();
+                (x, c)
+            })
+            .collect()
+    }
+
+    /// `None` if the sequence has too many queues to count.
+    fn seq(nodes: Vec<Node>) -> Option<Self> {
+        let nodes = Self::counted(nodes);
+        product(nodes.iter().map(|x| x.1))?;
+        Some(Self::Seq(nodes))
+    }
+
+    /// `None` if the selections have too many queues to count.
+    fn pick(choices: Vec<Option<Node>>, count: usize) -> Option<Self> {
+        let choices = Self::counted(choices.into_iter().collect::<Option<Vec<_>>>()?);
+        selections(choices.iter().map(|x| x.1), count)?;
+        Some(Self::Pick(choices, count))
+    }
+
+    fn count(&self) -> u128 {
+        // these were checked to fit when the node was built
+        match self {
+            Self::Piece(_) | Self::Hold(_) => 1,
+            Self::Seq(nodes) => product(nodes.iter().map(|x| x.1)).unwrap_or(u128::MAX),
+            Self::Pick(choices, count) => {
+                selections(choices.iter().map(|x| x.1), *count).unwrap_or(u128::MAX)
+            }
+        }
+    }
+
+    fn nth(&self, n: u128) -> Option<Queue> {
+        if n >= self.count() {
+            return None;
+        }
+
+        let mut queue = Queue::empty();
+        self.write_nth(n, &mut queue);
+        Some(queue)
+    }
+
+    /// Pushes the pieces of the `n`th queue of this node, where earlier nodes are the most significant.
+    fn write_nth(&self, mut n: u128, queue: &mut Queue) {
+        match self {
+            Self::Piece(p) => queue.pieces.push(*p),
+            Self::Hold(p) => queue.hold = Some(*p),
+            Self::Seq(nodes) => {
+                let mut rest = self.count();
+                for (node, count) in nodes {
+                    rest /= (*count).max(1);
+                    node.write_nth(n / rest, queue);
+                    n %= rest;
+                }
+            }
+            Self::Pick(choices, 1) => {
+                for (node, count) in choices {
+                    if n < *count {
+                        node.write_nth(n, queue);
+                        return;
                     }
 
-                    Part::Except(exclusions) => {
-                        // Define the full set of pieces
-                        let all_pieces = [
-                            Piece::I,
-                            Piece::J,
-                            Piece::O,
-                            Piece::L,
-                            Piece::Z,
-                            Piece::S,
-                            Piece::T,
-                        ]
-                        .map(Part::Single);
-
-                        // Compute the set difference: all pieces except the exclusions
-                        let filtered_pieces: Vec<Part> = all_pieces
+                    n -= count;
+                }
+            }
+            Self::Pick(choices, count) => {
+                let mut available = (0..choices.len()).vec();
+                for left in (0..*count).rev() {
+                    for (j, &i) in available.iter().enumerate() {
+                        let others = available
                             .iter()
-                            .filter(|piece| !exclusions.contains(piece))
-                            .cloned()
-                            .collect();
-
-                        // Add each filtered piece to the current queue
-                        for sub_part in filtered_pieces {
-                            let sub_iter = Iter::new(&Pattern {
-                                parts: vec![sub_part.clone()],
-                            });
-
-                            for sub_result in sub_iter {
-                                let mut combined = current.clone();
-                                combined.0.extend(sub_result);
-                                new_queue.push_back(combined);
-                            }
+                            .filter(|&&x| x != i)
+                            .map(|&x| choices[x].1);
+                        // both are at most the count of this node, so they fit
+                        let rest = selections(others, left).unwrap_or(u128::MAX);
+                        let block = choices[i].1 * rest;
+                        if n < block {
+                            choices[i].0.write_nth(n / rest, queue);
+                            n %= rest;
+                            available.remove(j);
+                            break;
                         }
-                    }
 
-                    Part::Wildcard => {
-                        for piece in [
-                            Piece::I,
-                            Piece::J,
-                            Piece::O,
-                            Piece::L,
-                            Piece::Z,
-                            Piece::S,
-                            Piece::T,
-                        ] {
-                            let mut combined = current.clone();
-                            combined.0.push(piece);
-                            new_queue.push_back(combined);
-                        }
+                        n -= block;
                     }
                 }
             }
-
-            self.queue = new_queue;
         }
     }
 }
 
-fn permutations<T: Clone>(items: &[T], k: usize) -> Vec<Vec<T>> {
-    if k == 0 {
-        return vec![vec![]];
+/// The amount of queues from picking `k` distinct choices in order, where each choice expands to the given amount of queues.
+/// This is `k!` times the elementary symmetric polynomial of degree `k` over the counts.
+/// `None` if that doesn't fit in a `u128`.
+fn selections(counts: impl Iterator<Item = u128> + Clone, k: usize) -> Option<u128> {
+    let len = counts.clone().count();
+    if k > len {
+        return Some(0);
     }
 
-    let mut result = vec![];
-    for (i, item) in items.iter().enumerate() {
-        let mut rest = items.to_vec();
-        rest.remove(i);
-        for mut sub_perm in permutations(&rest, k - 1) {
-            let mut perm = vec![item.clone()];
-            perm.append(&mut sub_perm);
-            result.push(perm);
+    // every choice usually expands to the same amount, which has a closed form
+    let first = counts.clone().next().unwrap_or(1);
+    if counts.clone().all(|x| x == first) {
+        return (0..k).try_fold(1u128, |a, i| a.checked_mul((len - i) as u128)?.checked_mul(first));
+    }
+
+    let mut e = vec![0u128; k + 1];
+    e[0] = 1;
+    for c in counts {
+        for j in (1..=k).rev() {
+            e[j] = e[j].checked_add(e[j - 1].checked_mul(c)?)?;
         }
     }
-    result
+
+    (1..=k as u128).try_fold(e[k], u128::checked_mul)
+}
+
+/// The product of `counts`, or `None` if that doesn't fit in a `u128`.
+fn product(mut counts: impl Iterator<Item = u128>) -> Option<u128> {
+    counts.try_fold(1u128, u128::checked_mul)
+}
+
+/// Lazily yields the queues of a [`Pattern`] one at a time, in a deterministic order.
+/// Skipping ahead with [`Iterator::nth`] is cheap, so a range of queues can be handed to each worker.
+#[derive(Clone, Debug)]
+pub struct Iter {
+    node: Node,
+    index: u128,
+    end: u128,
+}
+
+impl Iter {
+    pub fn new(pattern: &Pattern) -> Result<Self, String> {
+        let node = pattern.node().ok_or(TOO_MANY)?;
+        let end = node.count();
+        Ok(Self {
+            node,
+            index: 0,
+            end,
+        })
+    }
+
+    /// Restricts the iterator to the queues with indices in `range`.
+    #[must_use]
+    pub fn range(mut self, range: std::ops::Range<u128>) -> Self {
+        self.end = self.end.min(range.end);
+        self.index = range.start.min(self.end);
+        self
+    }
 }
 
 impl Iterator for Iter {
     type Item = Queue;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.queue.pop_front()
+        if self.index >= self.end {
+            return None;
+        }
+
+        let q = self.node.nth(self.index);
+        self.index += 1;
+        q
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.index = self.index.saturating_add(n as u128).min(self.end);
+        self.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = usize::try_from(self.end - self.index).ok();
+        (left.unwrap_or(usize::MAX), left)
     }
 }
 
 impl IntoIterator for Pattern {
     type Item = Queue;
     type IntoIter = Iter;
+    /// Panics if the pattern has more than `u128::MAX` queues, which parsed patterns never do.
     fn into_iter(self) -> Self::IntoIter {
-        Iter::new(&self)
+        Iter::new(&self).unwrap_or_else(|e| panic!("{e}"))
     }
 }
 
@@ -459,7 +802,8 @@ pub mod parse {
     use std::str::FromStr;
 
     use chumsky::{
-        prelude::{choice, group, just, one_of},
+        error::Rich,
+        prelude::{choice, group, just, one_of, recursive},
         text, IterParser, Parser,
     };
 
@@ -467,41 +811,229 @@ pub mod parse {
 
     use super::{Part, Pattern};
 
-    pub fn parser<'a>() -> impl Parser<'a, &'a str, Pattern> {
+    #[must_use]
+    pub fn parser<'a>() -> impl Parser<'a, &'a str, Pattern, chumsky::extra::Err<Rich<'a, char>>> {
+        let ws = text::inline_whitespace();
         let piece = one_of("IJOLZSTijolzst")
             .map(|x: char| Piece::from_str(&x.to_string()))
-            .unwrapped()
-            .map(Part::Single);
-        let wildcard = just("*").to(Part::Wildcard);
-        let bag_except = just("^")
-            .ignore_then(piece.repeated().at_least(1).collect())
-            .delimited_by(just("["), just("]"))
-            .map(Part::Except);
-        let bag = piece
-            .repeated()
-            .at_least(1)
-            .collect()
-            .delimited_by(just("["), just("]"))
-            .map(Part::Bag);
-        let repeatable = choice((bag_except, bag, wildcard, piece));
-        let count = group((repeatable.clone(), text::int(10).from_str().unwrapped()))
-            .map(|(x, y)| Part::Count(Box::new(x), y));
-        let all = group((repeatable.clone(), just("!"))).map(|(x, _)| Part::All(Box::new(x)));
-        let part = choice((all, count, repeatable));
-        let sq = part
-            .separated_by(just(",").or_not())
-            .allow_trailing()
-            .collect()
-            .map(|x| Pattern { parts: x });
+            .unwrapped();
+        let single = piece.map(Part::Single);
+        let part = recursive(|part| {
+            let wildcard = just("*").to(Part::Wildcard);
+            let bag_except = just("^")
+                .ignore_then(single.repeated().at_least(1).collect())
+                .delimited_by(just("["), just("]"))
+                .map(Part::Except);
+            let bag = single
+                .repeated()
+                .at_least(1)
+                .collect()
+                .delimited_by(just("["), just("]"))
+                .map(Part::Bag);
+            // a parenthesized group chooses one of its alternatives, like a bag of sequences
+            let parens = part
+                .separated_by(just(",").or_not())
+                .allow_trailing()
+                .collect()
+                .map(|x| Pattern { parts: x })
+                .separated_by(just("|"))
+                .at_least(1)
+                .collect()
+                .padded_by(ws)
+                .delimited_by(just("("), just(")"))
+                .map(Part::Or);
+            let repeatable = choice((parens, bag_except, bag, wildcard, single));
+            // sfinder writes counts as `*p7`, the `p` is optional here
+            let count = group((
+                repeatable.clone(),
+                one_of("pP").or_not(),
+                text::int(10).from_str().unwrapped(),
+            ))
+            .validate(|(x, _, y), e, emitter| {
+                let len = x.choices().len();
+                if y > len {
+                    let message = match x {
+                        Part::Or(_) => format!("cannot take {y} alternatives from a group that only has {len}"),
+                        _ => format!("cannot take {y} pieces from a bag that only has {len}"),
+                    };
+                    emitter.emit(Rich::custom(e.span(), message));
+                }
+
+                Part::Count(Box::new(x), y)
+            });
+            let all = group((repeatable.clone(), just("!"))).map(|(x, _)| Part::All(Box::new(x)));
+            choice((all, count, repeatable)).padded_by(ws)
+        });
+        // sfinder's quiz notation starts with the held piece in parentheses, like `(T)IJSZ`
+        let hold = piece
+            .padded_by(ws)
+            .delimited_by(just("("), just(")"))
+            .map(Part::Hold)
+            .padded_by(ws)
+            .then_ignore(just(",").or_not());
+        let sq = hold
+            .or_not()
+            .then(part.separated_by(just(",").or_not()).allow_trailing().collect::<Vec<_>>())
+            .map(|(hold, parts)| Pattern {
+                parts: hold.into_iter().chain(parts).collect(),
+            });
 
-        let or = sq
-            .separated_by(just("|"))
+        sq.separated_by(just("|"))
             .at_least(1)
             .collect()
             .map(|x| Pattern {
                 parts: vec![Part::Or(x)],
-            });
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use itertools::Itertools;
+
+    use super::{selections, Iter, Pattern, Queue};
+    use crate::{rng::Rng, traits::CollectVec};
+
+    fn pattern(s: &str) -> Pattern {
+        s.parse().unwrap()
+    }
+
+    fn queues(s: &str) -> Vec<String> {
+        pattern(s).queues().iter().map(Queue::to_string).vec()
+    }
+
+    #[test]
+    fn counts_queues_without_expanding() {
+        for (s, count) in [
+            ("*", 7),
+            ("*p3", 210),
+            ("*p7", 5040),
+            ("*p7,*p7", 25_401_600),
+            ("*p7,*p7,*p7,*p7,*p7,*p7,*p7,*p7,*p7,*p7", 5040u128.pow(10)),
+            ("[IJO]!", 6),
+            ("[^IJO]p2", 12),
+            ("I,*", 7),
+            ("IJ|OL|SZT", 3),
+            ("([IJ]|O)p2", 4),
+            ("(T)*p2", 42),
+        ] {
+            assert_eq!(pattern(s).count(), Ok(count), "{s}");
+        }
+
+        // past u128::MAX, which 5040 to the 11th is
+        assert!("*p7,*p7,*p7,*p7,*p7,*p7,*p7,*p7,*p7,*p7,*p7"
+            .parse::<Pattern>()
+            .is_err());
+    }
+
+    #[test]
+    fn counts_match_the_queues() {
+        for s in [
+            "*p3",
+            "[IJO]!,T",
+            "([IJ]|O)p2",
+            "(IJ|[SZ]p2|T)p2",
+            "(O)[IT]p2",
+            "I|J,*",
+        ] {
+            let queues = pattern(s).queues();
+            assert_eq!(queues.len() as u128, pattern(s).count().unwrap(), "{s}");
+            assert!(queues.iter().all_unique(), "{s}");
+        }
+
+        assert_eq!(queues("([IJ]|O)p2"), ["IO", "JO", "OI", "OJ"]);
+        assert_eq!(queues("(IJ|O)p2"), ["IJO", "OIJ"]);
+        assert_eq!(queues("(T)[IO]"), ["(T)I", "(T)O"]);
+    }
+
+    #[test]
+    fn selections_are_symmetric_polynomials() {
+        // 2 of [1, 2, 3] in order is 2! * (1*2 + 1*3 + 2*3)
+        assert_eq!(selections([1, 2, 3].into_iter(), 2), Some(22));
+        assert_eq!(selections([1, 2, 3].into_iter(), 3), Some(36));
+        assert_eq!(selections([5; 7].into_iter(), 3), Some(7 * 6 * 5 * 125));
+        assert_eq!(selections([1, 2].into_iter(), 3), Some(0));
+        assert_eq!(selections([u128::MAX, 2].into_iter(), 2), None);
+    }
+
+    #[test]
+    fn indexes_queues_in_order() {
+        let p = pattern("*p4");
+        let all = p.queues();
+        assert_eq!(all[0].to_string(), "IJOL");
+        assert_eq!(all[839].to_string(), "TSZL");
+
+        let range = Iter::new(&p).unwrap().range(100..110).vec();
+        assert_eq!(range, all[100..110]);
+        assert_eq!(Iter::new(&p).unwrap().nth(500), Some(all[500].clone()));
+        assert_eq!(Iter::new(&p).unwrap().range(830..900).count(), 10);
+
+        // the last of the 25401600 queues, without going through the others
+        let big = Iter::new(&pattern("*p7,*p7")).unwrap();
+        assert_eq!(
+            big.range(25_401_599..u128::MAX).vec()[0].to_string(),
+            "TSZLOJITSZLOJI"
+        );
+    }
+
+    #[test]
+    fn samples_are_queues_of_the_pattern() {
+        let p = pattern("[IJO]p2,*");
+        let all: HashSet<Queue> = p.queues().into_iter().collect();
+        let samples = p.samples(100, &mut Rng::new(7)).unwrap();
+        assert_eq!(samples.len(), 100);
+        assert!(samples.iter().all(|x| all.contains(x)));
+        assert_eq!(samples, p.samples(100, &mut Rng::new(7)).unwrap());
+    }
+
+    #[test]
+    fn reads_pattern_files() {
+        let p = pattern("# openers\nIJ\n\nOL  # second\n*p2");
+        assert_eq!(p.count(), Ok(44));
+        assert!("IJ\nOQ"
+            .parse::<Pattern>()
+            .unwrap_err()
+            .starts_with("on line 2"));
+    }
+
+    #[test]
+    fn compresses_back_to_the_same_queues() {
+        for s in ["*p7", "[IJO]!,T", "I,*p3|O,*p2", "(T)[SZ]p2"] {
+            let p = pattern(s);
+            let compressed = Pattern::from_queues(p.queues()).unwrap();
+            let sorted = |p: &Pattern| p.queues().into_iter().sorted_by_key(Queue::to_string).vec();
+            assert_eq!(sorted(&compressed), sorted(&p), "{s} -> {compressed}");
+        }
+
+        assert_eq!(
+            Pattern::from_queues(pattern("*p7").queues())
+                .unwrap()
+                .to_string(),
+            "*p7"
+        );
+    }
+
+    #[test]
+    fn orders_with_hold() {
+        let q: Queue = "IJO".parse().unwrap();
+        assert_eq!(q.hold_queues(None).len(), 4);
+        assert_eq!(q.hold_queues(Some(0)).len(), 1);
+        // placing the held piece at the end uses hold again
+        assert_eq!(q.hold_queues(Some(1)).len(), 1);
+        assert_eq!(q.hold_queues(Some(2)).len(), 3);
+        assert_eq!(
+            q.hold_equivalent(),
+            "JIO".parse::<Queue>().unwrap().hold_equivalent()
+        );
+        assert_ne!(
+            q.hold_equivalent(),
+            "IOJ".parse::<Queue>().unwrap().hold_equivalent()
+        );
 
-        or
+        let held: Queue = "(T)IJ".parse().unwrap();
+        assert_eq!(held.total(), 3);
+        assert_eq!(held.hold_queues(None).len(), 4);
     }
 }
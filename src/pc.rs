@@ -0,0 +1,250 @@
+use std::{collections::BTreeSet, str::FromStr};
+
+use crate::{bits::Bits, board::Board, piece::Piece, placement::Placement};
+
+/// A check that rules out fields that a perfect clear can't be finished from,
+/// so the search doesn't place the rest of the queue on them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Prune {
+    /// The empty cells must be exactly the cells of the queue.
+    /// Every placement below the top of the perfect clear fills 4 of them, so this is only checked before searching.
+    Cells,
+    /// Columns that are filled up to the top of the perfect clear split the field into parts that no piece
+    /// can reach across, so each part must have a multiple of 4 empty cells.
+    /// Clearing a line takes a cell from every column, so a filled column stays filled.
+    Regions,
+    /// The pieces left must be able to fill as many empty cells in even columns as in odd columns.
+    /// Clearing a line takes as many cells from both, and moving cells down doesn't change their column.
+    Columns,
+}
+
+impl FromStr for Prune {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cells" => Ok(Self::Cells),
+            "regions" => Ok(Self::Regions),
+            "columns" => Ok(Self::Columns),
+            _ => Err(format!(
+                "unknown check `{s}`, expected cells, regions or columns"
+            )),
+        }
+    }
+}
+
+/// A perfect clear of the bottom `lines` rows.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PerfectClear {
+    pub lines: usize,
+    pub prune: Vec<Prune>,
+}
+
+impl PerfectClear {
+    /// Whether every filled cell of `field` is in a full row below the top of the perfect clear.
+    #[must_use]
+    pub fn is_cleared(&self, field: &Bits) -> bool {
+        field
+            .filled_cells()
+            .into_iter()
+            .all(|(_, y)| y < self.lines && full_row(field, y))
+    }
+
+    /// Whether `placement` stays below the top of the perfect clear on `board`, which every placement of it does.
+    #[must_use]
+    pub fn fits(&self, board: &Board, placement: Placement) -> bool {
+        board
+            .adjusted_piece_offsets(placement)
+            .is_some_and(|x| x.into_iter().all(|(_, y)| y < self.lines))
+    }
+
+    /// Whether `queue` could finish the perfect clear from `field` before anything is placed,
+    /// which is where the cell count is checked.
+    #[must_use]
+    pub fn can_start(&self, field: &Bits, queue: &[Piece]) -> bool {
+        self.can_finish(field, queue)
+            && (!self.prune.contains(&Prune::Cells)
+                || self.area(field).empty_cells().len() == 4 * queue.len())
+    }
+
+    /// Whether `remaining` could still finish the perfect clear from `field`, as far as the checks can tell.
+    /// Fields with cells above the top of the perfect clear are always ruled out.
+    #[must_use]
+    pub fn can_finish(&self, field: &Bits, remaining: &[Piece]) -> bool {
+        if field
+            .filled_cells()
+            .into_iter()
+            .any(|(_, y)| y >= self.lines)
+        {
+            return false;
+        }
+
+        let area = self.area(field);
+        self.prune.iter().all(|check| match check {
+            Prune::Cells => true,
+            Prune::Regions => parts(&area).into_iter().all(|x| x % 4 == 0),
+            Prune::Columns => {
+                let empty = area.empty_cells();
+                let even = empty.iter().filter(|(x, _)| x % 2 == 0).count();
+                let difference = even.cast_signed() - (empty.len() - even).cast_signed();
+                column_differences(remaining).contains(&difference)
+            }
+        })
+    }
+
+    /// The rows of `field` below the top of the perfect clear that haven't been cleared yet.
+    fn area(&self, field: &Bits) -> Bits {
+        let mut area = field.clone();
+        area.height = self.lines.min(field.height);
+        area.bits.truncate(area.width * area.height);
+        area.skim();
+        area
+    }
+}
+
+fn full_row(field: &Bits, y: usize) -> bool {
+    (0..field.width).all(|x| field.get(x, y))
+}
+
+/// The amount of empty cells between each pair of filled columns of `area`, counting the walls as filled.
+fn parts(area: &Bits) -> Vec<usize> {
+    let mut sizes = vec![0];
+    for x in 0..area.width {
+        let empty = (0..area.height).filter(|&y| !area.get(x, y)).count();
+        if empty == 0 {
+            sizes.push(0);
+        } else {
+            *sizes.last_mut().unwrap() += empty;
+        }
+    }
+
+    sizes
+}
+
+/// Every difference between the cells in even and odd columns that `pieces` can cover together.
+fn column_differences(pieces: &[Piece]) -> BTreeSet<isize> {
+    pieces.iter().fold(BTreeSet::from([0]), |sums, piece| {
+        let options: &[isize] = match piece {
+            Piece::I => &[0, 4, -4],
+            Piece::T => &[0, 2, -2],
+            Piece::L | Piece::J => &[2, -2],
+            _ => &[0],
+        };
+        sums.iter()
+            .flat_map(|s| options.iter().map(move |o| s + o))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use itertools::Itertools;
+
+    use super::{column_differences, parts, PerfectClear, Prune};
+    use crate::{
+        board::Board, field::field_to_grid, pattern::Pattern, piece::Piece, placement::Placement,
+        traits::CollectVec,
+    };
+
+    fn board(field: &str) -> Board {
+        field_to_grid(field).unwrap().pages()[0].clone()
+    }
+
+    /// Every way to perfect clear `board` with the pieces of `queue` in order, ignoring whether they can be reached.
+    fn solutions(board: &Board, queue: &[Piece], pc: &PerfectClear) -> Vec<Vec<Placement>> {
+        let field = board.fast();
+        if !pc.can_finish(&field, queue) {
+            return vec![];
+        }
+        let Some((&piece, rest)) = queue.split_first() else {
+            return if pc.is_cleared(&field) {
+                vec![vec![]]
+            } else {
+                vec![]
+            };
+        };
+
+        field
+            .all_placements_of_piece(piece)
+            .into_iter()
+            .filter(|&p| pc.fits(board, p))
+            .flat_map(|p| {
+                solutions(&board.with_placement(p), rest, pc)
+                    .into_iter()
+                    .map(move |mut x| {
+                        x.insert(0, p);
+                        x
+                    })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pruning_keeps_every_solution() {
+        let unpruned = PerfectClear {
+            lines: 2,
+            prune: vec![],
+        };
+        let pruned = PerfectClear {
+            lines: 2,
+            prune: vec![Prune::Cells, Prune::Regions, Prune::Columns],
+        };
+
+        // the bottom row can clear on its own before the last piece
+        let mut total = 0;
+        for (field, pattern) in [
+            ("3\nX_____\nXXX___", "*,*"),
+            ("XX________\nXXXXXX____", "*,*,*"),
+        ] {
+            let board = board(field);
+            for queue in pattern.parse::<Pattern>().unwrap().queues() {
+                let expected = solutions(&board, queue.pieces(), &unpruned)
+                    .into_iter()
+                    .sorted()
+                    .vec();
+                let found = if pruned.can_start(&board.fast(), queue.pieces()) {
+                    solutions(&board, queue.pieces(), &pruned)
+                        .into_iter()
+                        .sorted()
+                        .vec()
+                } else {
+                    vec![]
+                };
+                assert_eq!(found, expected, "{queue} on {field:?}");
+                total += expected.len();
+            }
+        }
+        assert!(total > 0);
+    }
+
+    #[test]
+    fn regions_split_at_filled_columns() {
+        let field = board("X___X_____").fast();
+        assert_eq!(parts(&field), vec![0, 3, 5]);
+
+        let pc = |prune| PerfectClear {
+            lines: 1,
+            prune: vec![prune],
+        };
+        assert!(pc(Prune::Cells).can_start(&field, &[Piece::I, Piece::I]));
+        assert!(!pc(Prune::Regions).can_finish(&field, &[Piece::I, Piece::I]));
+        assert!(!pc(Prune::Cells).can_start(&field, &[Piece::I]));
+    }
+
+    #[test]
+    fn columns_count_pieces_that_can_fill_them() {
+        assert_eq!(
+            column_differences(&[Piece::O, Piece::S]),
+            BTreeSet::from([0])
+        );
+        assert_eq!(
+            column_differences(&[Piece::L, Piece::J]),
+            BTreeSet::from([-4, 0, 4])
+        );
+        assert_eq!(
+            column_differences(&[Piece::I, Piece::T]),
+            BTreeSet::from([-6, -4, -2, 0, 2, 4, 6])
+        );
+    }
+}
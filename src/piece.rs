@@ -7,7 +7,19 @@ use strum::{Display, EnumIter, EnumString};
 use crate::{data::placements::PLACEMENTS, input::Key, traits::GetWith};
 
 #[derive(
-    Clone, Copy, Debug, PartialEq, Eq, Display, EnumString, Hash, Serialize, Deserialize, EnumIter,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Display,
+    EnumString,
+    Hash,
+    Serialize,
+    Deserialize,
+    EnumIter,
 )]
 #[strum(ascii_case_insensitive)]
 pub enum Piece {
@@ -85,7 +97,9 @@ impl Piece {
     }
 }
 
-#[derive(Clone, Copy, Debug, strum::EnumIter, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(
+    Clone, Copy, Debug, strum::EnumIter, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 pub enum Rotation {
     North,
     East,
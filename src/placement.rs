@@ -10,7 +10,7 @@ use crate::{
     program::Handling,
 };
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Placement(Piece, usize, usize, Rotation);
 
 impl Placement {
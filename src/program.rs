@@ -1,4 +1,9 @@
-use std::{collections::HashSet, fmt::Write, io::Write as iW, time::Instant};
+use std::{
+    collections::HashSet,
+    fmt::{Display, Write},
+    io::Write as iW,
+    time::Instant,
+};
 
 use clap::Parser;
 use itertools::Itertools;
@@ -7,11 +12,15 @@ use crate::{
     board::Board,
     board_parser::Tetfu,
     data::kick::Kickset,
+    eval::{Metric, Ranking, Weights},
     grid::Grid,
     input::{DropType, Key},
     pattern::{Pattern, Queue},
+    pc::{PerfectClear, Prune},
     piece::{Piece, Rotation},
+    randomizer::Randomizer,
     ranged::Ranged,
+    shard::Shard,
     text::Text,
 };
 
@@ -44,6 +53,9 @@ pub struct Options {
     #[arg(short = 'h', long = "height")]
     /// The assumed height of the given board.
     pub board_height: Option<usize>,
+    #[arg(long = "threads")]
+    /// How many threads to search with, where 1 runs everything on the main thread. Defaults to one per core.
+    pub threads: Option<usize>,
     #[arg(short = 's', long = "stopwatch", default_value = "false")]
     /// Whether or not to output timing results.
     pub stopwatch: bool,
@@ -61,6 +73,9 @@ pub struct Options {
     #[arg(short = 'q', long = "no-hold", default_value = "false")]
     /// Whether or not the engine is allowed to use hold.
     pub no_hold: bool,
+    #[arg(long = "max-holds")]
+    /// The most times the engine is allowed to press hold for one queue.
+    pub max_holds: Option<usize>,
     #[arg(short = 'c', long = "no-comments", default_value = "false")]
     /// Whether or not to include page comments in the output. This significantly shortens fumen URLs.
     pub no_comments: bool,
@@ -68,8 +83,17 @@ pub struct Options {
     pub page_sep: String,
     #[arg(long = "rsep", default_value = "|")]
     pub row_sep: String,
+    #[arg(long = "bsep", default_value = "\\n")]
+    /// For the batch command, what to put between the results of each line.
+    pub batch_sep: String,
     #[arg(long = "raw", default_value = "false")]
     pub raw: bool,
+    #[arg(long = "page")]
+    /// Which page of the input to operate on, starting from 1. Defaults to the first page.
+    pub page: Option<usize>,
+    #[arg(long = "all-pages", conflicts_with = "page")]
+    /// Whether or not to operate on every page of the input. The outputs of each page are concatenated.
+    pub all_pages: bool,
 }
 
 #[derive(clap::Args, Clone, Debug, PartialEq, Eq)]
@@ -124,12 +148,18 @@ impl Handling {
 }
 
 #[derive(Clone, Debug, clap::Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum SfceCommand {
     #[command(subcommand)]
     Fumen(FumenCli),
     #[command(subcommand)]
     Pattern(PatternCli),
     Test,
+    /// Runs one command per line of the input, sharing the options and loaded kickset between them.
+    Batch {
+        #[arg(short = 'f', default_value = "-")]
+        commands: Text<String>,
+    },
     Grid {
         #[arg(short = 't')]
         tetfu: Text<Tetfu>,
@@ -144,6 +174,93 @@ pub enum SfceCommand {
         total_line_clears: Ranged<usize>,
         #[arg(short = 'q', default_value = "..")]
         continuous_line_clears: Ranged<usize>,
+        /// Only try this many uniformly sampled queues, and estimate how many queues of the pattern have a solution.
+        /// The estimate is printed to stderr.
+        #[arg(long = "sample")]
+        sample: Option<usize>,
+        /// The seed for `--sample`, picked from the clock if not given.
+        #[arg(long = "seed", requires = "sample")]
+        seed: Option<u64>,
+        /// Only search part `i` of `n` equal parts of the pattern, and write the results to a file for `merge`.
+        #[arg(long = "shard", conflicts_with = "sample")]
+        shard: Option<Shard>,
+        /// Where to write the results of `--shard`. Defaults to `shard-i-of-n.bin`.
+        #[arg(long = "shard-file", requires = "shard")]
+        shard_file: Option<String>,
+        /// Also record every result in this solution database, creating it if needed.
+        #[arg(long = "db")]
+        db: Option<String>,
+        /// Order the results by this metric, best first, or by `score` to weigh all of them.
+        #[arg(long = "sort-by", conflicts_with = "shard")]
+        sort_by: Option<Metric>,
+        /// Only output the best this many results, by `score` unless `--sort-by` is given.
+        #[arg(long = "top", conflicts_with = "shard")]
+        top: Option<usize>,
+        /// The weights of each metric in the score, as lines like `holes = -4`.
+        /// The results are ordered by score unless `--sort-by` is given.
+        #[arg(long = "weights", conflicts_with = "shard")]
+        weights: Option<Text<Weights>>,
+        /// Only find perfect clears of this many lines.
+        #[arg(long = "pc")]
+        pc: Option<usize>,
+        /// Skip fields the perfect clear can't be finished from, by the checks in this list:
+        /// cells, regions and columns. None of them ever skips a solution.
+        #[arg(long = "prune", value_delimiter = ',', requires = "pc")]
+        prune: Vec<Prune>,
+    },
+
+    /// Looks up solutions recorded by `move --db`.
+    #[command(subcommand)]
+    Db(DbCli),
+
+    /// Prints the shape of each page: cell counts, parity, holes, wells and how many lines a perfect clear needs.
+    Stats {
+        #[arg(short = 't')]
+        tetfu: Text<Tetfu>,
+    },
+
+    /// Finds every way to fill the empty cells of the bottom lines exactly with the pieces of a queue,
+    /// ignoring gravity and whether the pieces can get there.
+    Tile {
+        #[arg(short = 't')]
+        tetfu: Text<Tetfu>,
+        /// The pieces to fill with. Queues with the same pieces in a different order are only tiled once.
+        #[arg(short = 'p')]
+        pattern: Text<Pattern>,
+        /// How many lines from the bottom to fill, by default the fewest a perfect clear could use.
+        #[arg(short = 'l', long = "lines")]
+        lines: Option<usize>,
+        /// Stop after this many tilings.
+        #[arg(short = 'n', long = "limit")]
+        limit: Option<usize>,
+        /// Only keep tilings that can be placed in some order, and give them in that order.
+        #[arg(long = "doable")]
+        doable: bool,
+    },
+
+    /// Combines the result files of every shard of one `move --shard` search into the output `move` would have given.
+    Merge {
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+
+    /// Works out where a 7-bag game is from the pieces on the board and the queue, and what it deals next.
+    Bag {
+        #[arg(short = 't')]
+        tetfu: Text<Tetfu>,
+        /// The visible queue, starting with the current piece or with the held piece in parentheses like `(T)SZO`.
+        #[arg(short = 'p', default_value = "")]
+        queue: Queue,
+        /// The piece in hold, if any, for queues that don't already start with it.
+        #[arg(long = "hold")]
+        hold: Option<Piece>,
+        /// How many pieces after the queue to describe.
+        #[arg(short = 'l', long = "length", default_value = "7")]
+        length: usize,
+        /// How many pieces of its bag had been dealt before the first piece on the board, if known.
+        /// This is 0 for a game that started on this board without clearing lines.
+        #[arg(long = "offset")]
+        offset: Option<usize>,
     },
 
     Finesse {
@@ -201,6 +318,9 @@ pub enum FumenCli {
     Decode {
         #[arg(short = 't', long = "fumen")]
         fumen: Text<Tetfu>,
+        #[arg(long = "field")]
+        /// Whether or not to output in the multi-line format of sfinder's `field.txt`.
+        field: bool,
     },
 
     #[command(name = "glue")]
@@ -216,6 +336,35 @@ pub enum FumenCli {
     },
 }
 
+#[derive(clap::Subcommand, Clone, Debug)]
+pub enum DbCli {
+    /// Prints the recorded solutions that match every filter given.
+    #[command(name = "query")]
+    Query {
+        /// The solution database to read.
+        #[arg(long = "db")]
+        db: String,
+        /// Only solutions starting from one of the pages of this fumen.
+        #[arg(short = 't')]
+        tetfu: Option<Text<Tetfu>>,
+        /// Only solutions for one of the queues of this pattern.
+        #[arg(short = 'p')]
+        pattern: Option<Text<Pattern>>,
+        /// Only solutions that clear this many lines.
+        #[arg(short = 'c', default_value = "..")]
+        line_clears: Ranged<usize>,
+        /// Only solutions that place this piece first.
+        #[arg(long = "first")]
+        first: Option<Piece>,
+        /// Only solutions that place this piece last.
+        #[arg(long = "last")]
+        last: Option<Piece>,
+        /// Only print how many solutions match.
+        #[arg(long = "count")]
+        count: bool,
+    },
+}
+
 #[derive(clap::Subcommand, Clone, Debug)]
 pub enum PatternCli {
     #[command(name = "expand")]
@@ -228,6 +377,88 @@ pub enum PatternCli {
         #[arg(short = 'p')]
         pattern: Text<Pattern>,
     },
+    /// Counts the queues of a pattern without expanding it.
+    #[command(name = "count")]
+    Count {
+        #[arg(short = 'p', long = "pattern")]
+        pattern: Text<Pattern>,
+    },
+    /// The queues of either pattern.
+    #[command(name = "union")]
+    Union {
+        #[command(flatten)]
+        pair: PatternPair,
+    },
+    /// The queues of the first pattern that the second pattern also has.
+    #[command(name = "intersect")]
+    Intersect {
+        #[command(flatten)]
+        pair: PatternPair,
+    },
+    /// The queues of the first pattern that the second pattern doesn't have.
+    #[command(name = "diff")]
+    Diff {
+        #[command(flatten)]
+        pair: PatternPair,
+    },
+    /// Whether both patterns have the same queues.
+    #[command(name = "equal")]
+    Equal {
+        #[command(flatten)]
+        pair: PatternPair,
+    },
+    /// Writes a list of queues as a short pattern that expands to exactly them.
+    #[command(name = "compress")]
+    Compress {
+        /// The queues, separated by whitespace, commas or `|`. Anything after a `#` is a comment.
+        #[arg(short = 'q', long = "queues")]
+        queues: Text<String>,
+    },
+    /// Deals queues from the start of games that use a randomizer.
+    #[command(name = "random")]
+    Random {
+        /// One of `7bag`, `14bag`, `memoryless`, `tgm`, `nes` or `7bag-noszo`.
+        #[arg(short = 'r', long = "randomizer", default_value = "7bag")]
+        randomizer: Randomizer,
+        /// How many pieces are in each queue.
+        #[arg(short = 'l', long = "length", default_value = "7")]
+        length: usize,
+        /// How many pieces each game deals before the queue starts.
+        #[arg(long = "skip", default_value = "0")]
+        skip: usize,
+        #[arg(short = 'n', default_value = "1")]
+        amount: usize,
+        /// Picked from the clock if not given.
+        #[arg(long = "seed")]
+        seed: Option<u64>,
+    },
+    /// Picks uniformly random queues out of a pattern.
+    #[command(name = "sample")]
+    Sample {
+        #[arg(short = 'p', long = "pattern")]
+        pattern: Text<Pattern>,
+        #[arg(short = 'n', default_value = "1")]
+        amount: usize,
+        /// Picked from the clock if not given.
+        #[arg(long = "seed")]
+        seed: Option<u64>,
+    },
+}
+
+/// The operands of the pattern set commands.
+#[derive(clap::Args, Clone, Debug)]
+pub struct PatternPair {
+    #[arg(short = 'a')]
+    pub first: Text<Pattern>,
+    #[arg(short = 'b')]
+    pub second: Text<Pattern>,
+    #[arg(long = "hold")]
+    /// Whether or not queues that can be played as each other using hold count as the same queue.
+    /// Only the first two pieces can trade places, so IJO and IOJ are different queues.
+    pub hold: bool,
+    #[arg(long = "expr")]
+    /// Whether or not to output the resulting queues as a single pattern instead of a list.
+    pub expr: bool,
 }
 
 impl Sfce {
@@ -245,6 +476,9 @@ impl Sfce {
         if program.args.row_sep == "\\n" {
             program.args.row_sep = "\n".to_string();
         }
+        if program.args.batch_sep == "\\n" {
+            program.args.batch_sep = "\n".to_string();
+        }
 
         Self {
             program,
@@ -254,8 +488,33 @@ impl Sfce {
 
     pub fn run(&mut self) -> anyhow::Result<()> {
         let i = Instant::now();
+        if let Some(n) = self.program.args.threads {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(n)
+                .use_current_thread()
+                .build_global()?;
+        }
         // dbg!(&self);
-        match self.program.sub.clone() {
+        self.run_command(self.program.sub.clone())?;
+
+        if let Some(s) = &self.program.args.output {
+            println!("--> wrote {} bytes to path", self.buf.len());
+            std::fs::write(s, self.buf.clone())?;
+        } else {
+            writeln!(std::io::stdout(), "{}", self.buf)?;
+        }
+
+        if self.program.args.stopwatch {
+            println!("--> took {:.3}s", i.elapsed().as_secs_f64());
+        }
+
+        Ok(())
+    }
+
+    /// Runs a single command, writing its result into the buffer.
+    pub fn run_command(&mut self, command: SfceCommand) -> anyhow::Result<()> {
+        match command {
+            SfceCommand::Batch { commands } => self.batch_command(&commands)?,
             SfceCommand::Fumen(l) => self.fumen_command(l)?,
             SfceCommand::Pattern(l) => self.pattern_command(l)?,
 
@@ -266,12 +525,50 @@ impl Sfce {
                 pattern,
                 total_line_clears,
                 continuous_line_clears,
+                sample,
+                seed,
+                shard,
+                shard_file,
+                db,
+                sort_by,
+                top,
+                weights,
+                pc,
+                prune,
             } => self.move_command(
                 tetfu.contents(),
                 pattern.contents(),
                 total_line_clears,
                 continuous_line_clears,
+                sample,
+                seed,
+                shard,
+                shard_file,
+                db,
+                (sort_by.is_some() || top.is_some() || weights.is_some()).then(|| Ranking {
+                    sort_by: sort_by.unwrap_or(Metric::Score),
+                    top,
+                    weights: weights.map(Text::contents).unwrap_or_default(),
+                }),
+                pc.map(|lines| PerfectClear { lines, prune }).as_ref(),
             )?,
+            SfceCommand::Db(d) => self.db_command(d)?,
+            SfceCommand::Stats { tetfu } => self.stats_command(&tetfu)?,
+            SfceCommand::Tile {
+                tetfu,
+                pattern,
+                lines,
+                limit,
+                doable,
+            } => self.tile_command(&tetfu, &pattern, lines, limit, doable)?,
+            SfceCommand::Merge { files } => self.merge_command(&files)?,
+            SfceCommand::Bag {
+                tetfu,
+                queue,
+                hold,
+                length,
+                offset,
+            } => self.bag_command(&tetfu, &queue, hold, length, offset)?,
             SfceCommand::Finesse {
                 tetfu,
                 piece,
@@ -293,22 +590,12 @@ impl Sfce {
             } => self.possible(&tetfu.contents(), piece)?,
         }
 
-        if let Some(s) = &self.program.args.output {
-            println!("--> wrote {} bytes to path", self.buf.len());
-            std::fs::write(s, self.buf.clone())?;
-        } else {
-            writeln!(std::io::stdout(), "{}", self.buf)?;
-        }
-
-        if self.program.args.stopwatch {
-            println!("--> took {:.3}s", i.elapsed().as_secs_f64());
-        }
-
         Ok(())
     }
 
-    #[must_use]
-    pub fn tetfu(&self, f: &Grid) -> String {
+    /// Formats `f` according to the link type, or as plain text if there is none.
+    /// Fumen links fail for boards larger than fumen supports, while text keeps the full board.
+    pub fn tetfu(&self, f: &Grid) -> anyhow::Result<String> {
         let mut g = if self.program.args.no_comments {
             Grid::from_pages(
                 f.0.clone()
@@ -324,15 +611,15 @@ impl Sfce {
         };
 
         if g.0.is_empty() {
-            g.add_page(Board::new(""));
+            g.add_page(Board::default());
         }
-        if let Some(t) = self.program.args.link_type {
+        Ok(if let Some(t) = self.program.args.link_type {
             if t.is_lowercase() {
-                format!("{t}{}", &g.fumen().encode()[1..])
+                format!("{t}{}", &g.fumen()?.encode()[1..])
             } else if t == 'Q' {
-                format!("https://qv.rqft.workers.dev/view?{}", g.fumen().encode())
+                format!("https://qv.rqft.workers.dev/view?{}", g.fumen()?.encode())
             } else if t == 'D' {
-                format!("https://fumen.zui.jp/?D{}", &g.fumen().encode()[1..])
+                format!("https://fumen.zui.jp/?D{}", &g.fumen()?.encode()[1..])
             } else if t == 'T' {
                 let mut z = String::new();
                 z += "\n";
@@ -368,15 +655,18 @@ impl Sfce {
                     z += "\n";
                 }
 
-                z += "https://qv.rqft.workers.dev/view?";
-                z += &g.fumen().encode();
+                // the colored field already shows boards of any size, so only link when fumen can hold it
+                if let Ok(f) = g.fumen() {
+                    z += "https://qv.rqft.workers.dev/view?";
+                    z += &f.encode();
+                }
 
                 z
             } else {
                 format!(
                     "https://harddrop.com/fumen?{}{}",
                     t.to_lowercase(),
-                    &g.fumen().encode()[1..]
+                    &g.fumen()?.encode()[1..]
                 )
             }
         } else {
@@ -389,7 +679,7 @@ impl Sfce {
                         .join(&self.program.args.row_sep)
                 })
                 .join(&self.program.args.page_sep)
-        }
+        })
     }
 
     #[must_use]
@@ -407,12 +697,38 @@ impl Sfce {
         f
     }
 
+    /// The pages of `tetfu` to operate on as chosen by `--page` or `--all-pages`, resized like [`Sfce::resize`].
+    pub fn selected_pages(&self, tetfu: &Tetfu) -> anyhow::Result<Vec<Board>> {
+        let pages = self.resize(tetfu.grid()).0;
+        if self.program.args.all_pages {
+            return Ok(pages);
+        }
+
+        let n = self.program.args.page.unwrap_or(1);
+        let total = pages.len();
+        pages
+            .into_iter()
+            .nth(n.wrapping_sub(1))
+            .map(|x| vec![x])
+            .ok_or(anyhow::anyhow!("page {n} does not exist, the input has {total} pages"))
+    }
+
+    /// Prefixes `comment` with the comment of the `source` page when operating on all pages,
+    /// so that every output page can be traced back to its input.
+    #[must_use]
+    pub fn page_comment(&self, source: &Board, comment: impl Display) -> String {
+        match source.comment() {
+            Some(c) if self.program.args.all_pages => format!("{c}: {comment}"),
+            _ => comment.to_string(),
+        }
+    }
+
     #[must_use]
     pub fn hold_queues(&self, queue: &Queue) -> HashSet<Queue> {
         if self.program.args.no_hold {
-            HashSet::from([queue.clone()])
+            queue.hold_queues(Some(0))
         } else {
-            queue.hold_queues()
+            queue.hold_queues(self.program.args.max_holds)
         }
     }
 }
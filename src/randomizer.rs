@@ -0,0 +1,166 @@
+use std::{collections::VecDeque, str::FromStr};
+
+use crate::{pattern::Queue, piece::Piece, rng::Rng};
+
+/// The 7 pieces in the order randomizers number them.
+const PIECES: [Piece; 7] = [
+    Piece::I,
+    Piece::J,
+    Piece::O,
+    Piece::L,
+    Piece::Z,
+    Piece::S,
+    Piece::T,
+];
+
+/// How a game picks its next pieces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Randomizer {
+    /// Every 7 pieces are a shuffle of all 7 pieces.
+    Bag7,
+    /// Every 14 pieces are a shuffle of two of each piece.
+    Bag14,
+    /// Every piece is equally likely, regardless of the previous ones.
+    Memoryless,
+    /// TGM's randomizer, which rerolls a piece up to 3 times (4 rolls in total) if it is one of the last 4 pieces.
+    /// The first piece is never S, Z or O.
+    Tgm,
+    /// The NES randomizer, which rerolls once if it picks the previous piece.
+    Nes,
+    /// A 7-bag where the first piece of the game is never S, Z or O.
+    Bag7NoSzoStart,
+}
+
+impl FromStr for Randomizer {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "7bag" | "bag" => Ok(Self::Bag7),
+            "14bag" => Ok(Self::Bag14),
+            "memoryless" | "random" => Ok(Self::Memoryless),
+            "tgm" | "history" => Ok(Self::Tgm),
+            "nes" => Ok(Self::Nes),
+            "7bag-noszo" => Ok(Self::Bag7NoSzoStart),
+            _ => Err("unknown randomizer".to_string()),
+        }
+    }
+}
+
+impl Randomizer {
+    /// An endless stream of pieces from this randomizer.
+    #[must_use]
+    pub fn stream(self, rng: Rng) -> Stream {
+        Stream {
+            randomizer: self,
+            rng,
+            upcoming: VecDeque::new(),
+            // TGM starts as if 4 Z pieces had been dealt
+            history: match self {
+                Self::Tgm => VecDeque::from([Piece::Z; 4]),
+                _ => VecDeque::new(),
+            },
+            dealt: 0,
+        }
+    }
+}
+
+/// The pieces a game deals, one at a time.
+#[derive(Clone, Debug)]
+pub struct Stream {
+    randomizer: Randomizer,
+    rng: Rng,
+    /// Pieces that have been decided but not dealt yet, such as the rest of a bag.
+    upcoming: VecDeque<Piece>,
+    /// The most recently dealt pieces, oldest first, for randomizers that avoid repeats.
+    history: VecDeque<Piece>,
+    dealt: usize,
+}
+
+impl Stream {
+    /// The next `len` pieces.
+    pub fn queue(&mut self, len: usize) -> Queue {
+        self.take(len).collect()
+    }
+
+    fn random_piece(&mut self) -> Piece {
+        PIECES[self.rng.index(PIECES.len())]
+    }
+
+    fn shuffled(&mut self, mut bag: Vec<Piece>) -> Vec<Piece> {
+        for i in (1..bag.len()).rev() {
+            bag.swap(i, self.rng.index(i + 1));
+        }
+
+        bag
+    }
+
+    fn refill(&mut self) {
+        let bag = match self.randomizer {
+            Randomizer::Bag14 => PIECES.repeat(2),
+            _ => PIECES.to_vec(),
+        };
+
+        loop {
+            let bag = self.shuffled(bag.clone());
+            let banned = self.randomizer == Randomizer::Bag7NoSzoStart
+                && self.dealt == 0
+                && matches!(bag[0], Piece::S | Piece::Z | Piece::O);
+            if !banned {
+                self.upcoming.extend(bag);
+                return;
+            }
+        }
+    }
+
+    fn next_tgm(&mut self) -> Piece {
+        if self.dealt == 0 {
+            return [Piece::I, Piece::J, Piece::L, Piece::T][self.rng.index(4)];
+        }
+
+        let mut piece = self.random_piece();
+        for _ in 1..4 {
+            if !self.history.contains(&piece) {
+                break;
+            }
+            piece = self.random_piece();
+        }
+
+        piece
+    }
+
+    fn next_nes(&mut self) -> Piece {
+        // the roll has an 8th outcome that always rerolls, like picking the previous piece does
+        let roll = self.rng.index(PIECES.len() + 1);
+        if roll < PIECES.len() && self.history.back() != Some(&PIECES[roll]) {
+            return PIECES[roll];
+        }
+
+        self.random_piece()
+    }
+}
+
+impl Iterator for Stream {
+    type Item = Piece;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let piece = match self.randomizer {
+            Randomizer::Bag7 | Randomizer::Bag14 | Randomizer::Bag7NoSzoStart => {
+                if self.upcoming.is_empty() {
+                    self.refill();
+                }
+                self.upcoming.pop_front()?
+            }
+            Randomizer::Memoryless => self.random_piece(),
+            Randomizer::Tgm => self.next_tgm(),
+            Randomizer::Nes => self.next_nes(),
+        };
+
+        self.history.push_back(piece);
+        if self.history.len() > 4 {
+            self.history.pop_front();
+        }
+        self.dealt += 1;
+
+        Some(piece)
+    }
+}
@@ -0,0 +1,50 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A small seedable random number generator (`SplitMix64`), so that sampled results can be reproduced from their seed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// A seed taken from the current time, for when the user doesn't give one.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn random_seed() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_nanos() as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A uniformly random number in `0..bound`. `bound` must not be 0.
+    pub fn below(&mut self, bound: u128) -> u128 {
+        assert!(bound > 0, "cannot pick a number below 0");
+        // reject the top of the range that doesn't divide evenly, so every result is equally likely
+        let zone = u128::MAX - (u128::MAX % bound + 1) % bound;
+        loop {
+            let x = (u128::from(self.next_u64()) << 64) | u128::from(self.next_u64());
+            if x <= zone {
+                return x % bound;
+            }
+        }
+    }
+
+    /// A uniformly random index into a slice of length `len`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn index(&mut self, len: usize) -> usize {
+        self.below(len as u128) as usize
+    }
+}
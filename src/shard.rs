@@ -0,0 +1,214 @@
+use std::{fmt::Display, fs::File, io::BufWriter, ops::Range, str::FromStr};
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{board::Board, placement::Placement};
+
+/// One of `count` equal parts of a search, written as `index/count` with `index` starting from 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Shard {
+    /// The indices of the queues out of `total` that this shard searches.
+    #[must_use]
+    pub fn range(self, total: u128) -> Range<u128> {
+        let at = |i: usize| {
+            total / self.count as u128 * i as u128 + (total % self.count as u128).min(i as u128)
+        };
+        at(self.index - 1)..at(self.index)
+    }
+
+    /// Where the results of this shard are written when no path is given.
+    #[must_use]
+    pub fn default_path(self) -> String {
+        format!("shard-{}-of-{}.bin", self.index, self.count)
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (i, n) = s
+            .split_once('/')
+            .ok_or_else(|| "expected a shard like `1/4`".to_string())?;
+        let index: usize = i
+            .trim()
+            .parse()
+            .map_err(|_| format!("`{i}` is not a number"))?;
+        let count: usize = n
+            .trim()
+            .parse()
+            .map_err(|_| format!("`{n}` is not a number"))?;
+        if count == 0 || index == 0 || index > count {
+            return Err(format!("shard {index} must be between 1 and {count}"));
+        }
+
+        Ok(Self { index, count })
+    }
+}
+
+impl Display for Shard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// The results of searching one shard, which `merge` combines with the results of the other shards.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShardResult {
+    pub shard: Shard,
+    /// The pattern, boards and arguments of the search, which every shard of it must share.
+    pub run: String,
+    /// How many queues were searched, counting each page separately.
+    pub queues: usize,
+    /// How many of those queues had at least one result.
+    pub solved: usize,
+    /// Every result, in the order `move` outputs them.
+    pub moves: Vec<(Vec<Placement>, Board)>,
+}
+
+impl ShardResult {
+    pub fn write(&self, path: &str) -> anyhow::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(file, self)?;
+        Ok(())
+    }
+
+    pub fn read(path: &str) -> anyhow::Result<Self> {
+        let file = std::io::BufReader::new(File::open(path)?);
+        bincode::deserialize_from(file)
+            .map_err(|e| anyhow::anyhow!("{path} is not a shard result file: {e}"))
+    }
+
+    /// Combines the results of every shard of one search into the results of the whole search,
+    /// which is its only shard.
+    pub fn merge(mut results: Vec<Self>) -> anyhow::Result<Self> {
+        results.sort_by_key(|x| x.shard);
+        let Some(first) = results.first() else {
+            anyhow::bail!("no shards to merge");
+        };
+
+        let count = first.shard.count;
+        if let Some(r) = results.iter().find(|x| x.shard.count != count) {
+            anyhow::bail!(
+                "shard {} doesn't belong with shards out of {count}",
+                r.shard
+            );
+        }
+        if let Some(r) = results.iter().find(|x| x.run != first.run) {
+            anyhow::bail!(
+                "shard {} comes from a different search than shard {}",
+                r.shard,
+                first.shard
+            );
+        }
+        if let Some((a, _)) = results
+            .iter()
+            .tuple_windows()
+            .find(|(a, b)| a.shard == b.shard)
+        {
+            anyhow::bail!("shard {} was given more than once", a.shard);
+        }
+
+        let missing = (1..=count)
+            .filter(|i| !results.iter().any(|x| x.shard.index == *i))
+            .map(|i| format!("{i}/{count}"))
+            .join(", ");
+        if !missing.is_empty() {
+            anyhow::bail!("missing shards {missing}");
+        }
+
+        Ok(Self {
+            shard: Shard { index: 1, count: 1 },
+            run: first.run.clone(),
+            queues: results.iter().map(|x| x.queues).sum(),
+            solved: results.iter().map(|x| x.solved).sum(),
+            moves: results.into_iter().flat_map(|x| x.moves).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Shard, ShardResult};
+    use crate::{board::Board, placement::Placement};
+
+    fn result(shard: &str, run: &str, moves: &[usize]) -> ShardResult {
+        let placements: Vec<Placement> = vec![];
+        ShardResult {
+            shard: shard.parse().unwrap(),
+            run: run.to_string(),
+            queues: 10,
+            solved: moves.len(),
+            moves: moves
+                .iter()
+                .map(|&x| (placements.clone(), Board::empty(x, 1, 0)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn shards_cover_every_queue_once() {
+        for total in [0, 1, 5, 7, 100, 5040, u128::MAX] {
+            for count in 1..=9 {
+                let ranges = (1..=count)
+                    .map(|index| Shard { index, count }.range(total))
+                    .collect::<Vec<_>>();
+                assert_eq!(ranges[0].start, 0);
+                assert_eq!(ranges[count - 1].end, total);
+                for w in ranges.windows(2) {
+                    assert_eq!(w[0].end, w[1].start);
+                }
+                let sizes = ranges.iter().map(|x| x.end - x.start).collect::<Vec<_>>();
+                assert!(sizes.iter().max().unwrap() - sizes.iter().min().unwrap() <= 1);
+            }
+        }
+
+        assert_eq!(Shard { index: 2, count: 3 }.range(10), 4..7);
+    }
+
+    #[test]
+    fn parses_shards() {
+        assert_eq!("2/4".parse(), Ok(Shard { index: 2, count: 4 }));
+        assert!("0/4".parse::<Shard>().is_err());
+        assert!("5/4".parse::<Shard>().is_err());
+        assert!("1/0".parse::<Shard>().is_err());
+        assert!("1".parse::<Shard>().is_err());
+    }
+
+    #[test]
+    fn merges_only_a_complete_set() {
+        let merged = ShardResult::merge(vec![
+            result("2/2", "a", &[3, 4]),
+            result("1/2", "a", &[1, 2]),
+        ])
+        .unwrap();
+        assert_eq!(merged.shard, Shard { index: 1, count: 1 });
+        assert_eq!((merged.queues, merged.solved), (20, 4));
+        let widths = merged.moves.iter().map(|x| x.1.width()).collect::<Vec<_>>();
+        assert_eq!(widths, [1, 2, 3, 4]);
+
+        let error = |results| ShardResult::merge(results).unwrap_err().to_string();
+        assert_eq!(
+            error(vec![result("1/3", "a", &[]), result("3/3", "a", &[])]),
+            "missing shards 2/3"
+        );
+        assert_eq!(
+            error(vec![result("1/2", "a", &[]), result("1/2", "a", &[])]),
+            "shard 1/2 was given more than once"
+        );
+        assert_eq!(
+            error(vec![result("1/2", "a", &[]), result("2/2", "b", &[])]),
+            "shard 2/2 comes from a different search than shard 1/2"
+        );
+        assert_eq!(
+            error(vec![result("1/2", "a", &[]), result("2/3", "a", &[])]),
+            "shard 2/3 doesn't belong with shards out of 2"
+        );
+        assert_eq!(error(vec![]), "no shards to merge");
+    }
+}
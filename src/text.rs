@@ -5,6 +5,8 @@ use std::{
 };
 
 use anyhow::anyhow;
+use chumsky::error::{Rich, RichPattern, RichReason};
+use itertools::Itertools;
 
 #[derive(Clone, Debug, Copy)]
 pub struct Text<T>(T);
@@ -16,7 +18,13 @@ where
 {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if let Some(f) = s.strip_prefix("file:") {
+        if s == "-" || s == "stdin:" {
+            Ok(Self(
+                std::io::read_to_string(std::io::stdin())?
+                    .parse()
+                    .map_err(|x| anyhow!("{x}"))?,
+            ))
+        } else if let Some(f) = s.strip_prefix("file:") {
             Ok(Self(
                 std::fs::read_to_string(f)?
                     .parse()
@@ -40,3 +48,54 @@ impl<T> Text<T> {
         self.0
     }
 }
+
+/// Renders the errors from parsing `src` as messages that point at the offending column with a caret
+/// and list what would have been allowed there instead.
+#[must_use]
+pub fn parse_error(src: &str, errors: &[Rich<'_, char>]) -> String {
+    errors
+        .iter()
+        .map(|e| {
+            let column = src[..e.span().start].chars().count();
+            if let RichReason::Custom(message) = e.reason() {
+                return format!(
+                    "{message} at column {}\n  {src}\n  {}^",
+                    column + 1,
+                    " ".repeat(column)
+                );
+            }
+
+            let found = e
+                .found()
+                .map_or("end of input".to_string(), |c| format!("`{c}`"));
+
+            let mut chars = String::new();
+            let mut expected = vec![];
+            for p in e.expected() {
+                match p {
+                    RichPattern::Token(t) => {
+                        if !chars.contains(**t) {
+                            chars.push(**t);
+                        }
+                    }
+                    RichPattern::Label(l) => expected.push((*l).to_string()),
+                    RichPattern::EndOfInput => expected.push("end of input".to_string()),
+                    // newer chumsky versions have more kinds of patterns
+                    #[allow(unreachable_patterns)]
+                    _ => expected.push(p.to_string()),
+                }
+            }
+
+            if !chars.is_empty() {
+                expected.insert(0, format!("one of `{chars}`"));
+            }
+
+            format!(
+                "unexpected {found} at column {}, expected {}\n  {src}\n  {}^",
+                column + 1,
+                expected.iter().join(" or "),
+                " ".repeat(column)
+            )
+        })
+        .join("\n")
+}
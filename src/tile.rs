@@ -0,0 +1,289 @@
+use std::collections::{BTreeMap, HashSet};
+
+use itertools::Itertools;
+use strum::IntoEnumIterator;
+
+use crate::{
+    piece::{Piece, Rotation},
+    placement::Placement,
+    traits::CollectVec,
+};
+
+/// Every way to cover exactly the cells of `region` with the pieces counted in `pieces`,
+/// using each piece as many times as it is counted and ignoring gravity and reachability.
+/// Stops after `limit` tilings if given.
+///
+/// The counts must add up to a quarter of the region, so that every tiling uses all of them.
+#[must_use]
+pub fn tilings(
+    region: &[(usize, usize)],
+    pieces: &BTreeMap<Piece, usize>,
+    limit: Option<usize>,
+) -> Vec<Vec<Placement>> {
+    if pieces.values().sum::<usize>() * 4 != region.len() {
+        return vec![];
+    }
+
+    let columns = region
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| (c, i + 1))
+        .collect::<BTreeMap<_, _>>();
+
+    // rotations that cover the same cells, like the 4 rotations of O, are only kept once
+    let mut seen = HashSet::new();
+    let mut rows = vec![];
+    let mut links = Links::new(region.len());
+    for &(x, y) in region {
+        for (&piece, rotation) in pieces.keys().cartesian_product(Rotation::iter()) {
+            let Some(cells) = piece.cells(x, y, rotation) else {
+                continue;
+            };
+            let Some(cover) = cells
+                .iter()
+                .map(|c| columns.get(c).copied())
+                .collect::<Option<Vec<_>>>()
+            else {
+                continue;
+            };
+            if seen.insert((piece, cells.into_iter().sorted().vec())) {
+                links.add_row(rows.len(), &cover);
+                rows.push(Placement::new(piece, x, y, rotation));
+            }
+        }
+    }
+
+    let mut search = Search {
+        links,
+        rows: &rows,
+        left: pieces.clone(),
+        chosen: vec![],
+        found: vec![],
+        limit,
+    };
+    search.run();
+    search.found
+}
+
+/// The exact cover matrix as circular doubly linked lists, so that covering and uncovering a column
+/// only relinks its neighbours. Node 0 is the root, and nodes 1 to the number of columns are the column headers.
+struct Links {
+    left: Vec<usize>,
+    right: Vec<usize>,
+    up: Vec<usize>,
+    down: Vec<usize>,
+    column: Vec<usize>,
+    row: Vec<usize>,
+    /// How many rows are left in each column, indexed by its header.
+    size: Vec<usize>,
+}
+
+impl Links {
+    fn new(columns: usize) -> Self {
+        let n = columns + 1;
+        Self {
+            left: (0..n).map(|i| (i + n - 1) % n).collect(),
+            right: (0..n).map(|i| (i + 1) % n).collect(),
+            up: (0..n).collect(),
+            down: (0..n).collect(),
+            column: (0..n).collect(),
+            row: vec![usize::MAX; n],
+            size: vec![0; n],
+        }
+    }
+
+    fn add_row(&mut self, row: usize, columns: &[usize]) {
+        let first = self.left.len();
+        for (i, &c) in columns.iter().enumerate() {
+            let node = first + i;
+            self.left.push(if i == 0 {
+                first + columns.len() - 1
+            } else {
+                node - 1
+            });
+            self.right.push(if i + 1 == columns.len() {
+                first
+            } else {
+                node + 1
+            });
+            self.up.push(self.up[c]);
+            self.down.push(c);
+            let above = self.up[c];
+            self.down[above] = node;
+            self.up[c] = node;
+            self.column.push(c);
+            self.row.push(row);
+            self.size[c] += 1;
+        }
+    }
+
+    fn cover(&mut self, c: usize) {
+        self.right[self.left[c]] = self.right[c];
+        self.left[self.right[c]] = self.left[c];
+        let mut i = self.down[c];
+        while i != c {
+            let mut j = self.right[i];
+            while j != i {
+                self.down[self.up[j]] = self.down[j];
+                self.up[self.down[j]] = self.up[j];
+                self.size[self.column[j]] -= 1;
+                j = self.right[j];
+            }
+            i = self.down[i];
+        }
+    }
+
+    fn uncover(&mut self, c: usize) {
+        let mut i = self.up[c];
+        while i != c {
+            let mut j = self.left[i];
+            while j != i {
+                self.size[self.column[j]] += 1;
+                self.down[self.up[j]] = j;
+                self.up[self.down[j]] = j;
+                j = self.left[j];
+            }
+            i = self.up[i];
+        }
+        self.right[self.left[c]] = c;
+        self.left[self.right[c]] = c;
+    }
+}
+
+/// Knuth's algorithm X over the cells of the region, only choosing pieces that haven't run out.
+struct Search<'a> {
+    links: Links,
+    rows: &'a [Placement],
+    left: BTreeMap<Piece, usize>,
+    chosen: Vec<Placement>,
+    found: Vec<Vec<Placement>>,
+    limit: Option<usize>,
+}
+
+impl Search<'_> {
+    fn run(&mut self) {
+        if self.limit.is_some_and(|x| self.found.len() >= x) {
+            return;
+        }
+
+        let links = &self.links;
+        if links.right[0] == 0 {
+            self.found.push(self.chosen.clone());
+            return;
+        }
+
+        // the cell with the fewest ways to cover it
+        let mut c = links.right[0];
+        let mut best = c;
+        while c != 0 {
+            if links.size[c] < links.size[best] {
+                best = c;
+            }
+            c = links.right[c];
+        }
+        if links.size[best] == 0 {
+            return;
+        }
+
+        self.links.cover(best);
+        let mut r = self.links.down[best];
+        while r != best {
+            let placement = self.rows[self.links.row[r]];
+            let count = self.left.get_mut(&placement.piece()).unwrap();
+            if *count > 0 {
+                *count -= 1;
+                self.chosen.push(placement);
+
+                let mut j = self.links.right[r];
+                while j != r {
+                    self.links.cover(self.links.column[j]);
+                    j = self.links.right[j];
+                }
+                self.run();
+                let mut j = self.links.left[r];
+                while j != r {
+                    self.links.uncover(self.links.column[j]);
+                    j = self.links.left[j];
+                }
+
+                self.chosen.pop();
+                *self.left.get_mut(&placement.piece()).unwrap() += 1;
+            }
+            r = self.links.down[r];
+        }
+        self.links.uncover(best);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::tilings;
+    use crate::{piece::Piece, traits::CollectVec};
+
+    fn rectangle(width: usize, height: usize) -> Vec<(usize, usize)> {
+        (0..height)
+            .cartesian_product(0..width)
+            .map(|(y, x)| (x, y))
+            .vec()
+    }
+
+    /// The tilings of `region`, checking that each covers it exactly.
+    fn count(region: &[(usize, usize)], pieces: &[(Piece, usize)], limit: Option<usize>) -> usize {
+        let found = tilings(region, &pieces.iter().copied().collect(), limit);
+        for tiling in &found {
+            let cells = tiling
+                .iter()
+                .flat_map(|p| p.piece().cells(p.x(), p.y(), p.rotation()).unwrap())
+                .sorted()
+                .vec();
+            assert_eq!(cells, region.iter().copied().sorted().vec(), "{tiling:?}");
+        }
+        found.len()
+    }
+
+    #[test]
+    fn tiles_rectangles() {
+        assert_eq!(count(&rectangle(4, 4), &[(Piece::I, 4)], None), 2);
+        assert_eq!(count(&rectangle(4, 2), &[(Piece::O, 2)], None), 1);
+        assert_eq!(count(&rectangle(4, 2), &[(Piece::I, 2)], None), 1);
+        assert_eq!(count(&rectangle(2, 4), &[(Piece::I, 2)], None), 1);
+        assert_eq!(count(&rectangle(4, 4), &[(Piece::T, 4)], None), 2);
+        assert_eq!(count(&rectangle(4, 2), &[(Piece::L, 2)], None), 1);
+        assert_eq!(count(&rectangle(4, 2), &[(Piece::J, 2)], None), 1);
+        assert_eq!(
+            count(&rectangle(4, 2), &[(Piece::L, 1), (Piece::J, 1)], None),
+            0
+        );
+        assert_eq!(
+            count(&rectangle(4, 2), &[(Piece::S, 1), (Piece::Z, 1)], None),
+            0
+        );
+    }
+
+    #[test]
+    fn uses_every_piece_as_often_as_counted() {
+        assert_eq!(count(&rectangle(4, 4), &[(Piece::I, 3)], None), 0);
+        assert_eq!(count(&rectangle(4, 4), &[(Piece::I, 5)], None), 0);
+        // the Is go in 2 of the rows or columns and the Os fill the rest
+        assert_eq!(
+            count(&rectangle(4, 4), &[(Piece::I, 2), (Piece::O, 2)], None),
+            6
+        );
+        assert_eq!(
+            count(
+                &rectangle(4, 4),
+                &[(Piece::I, 2), (Piece::O, 1), (Piece::T, 1)],
+                None
+            ),
+            0
+        );
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        assert_eq!(count(&rectangle(4, 4), &[(Piece::I, 4)], Some(1)), 1);
+        assert_eq!(count(&rectangle(4, 4), &[(Piece::I, 4)], Some(0)), 0);
+    }
+}